@@ -0,0 +1,176 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use serde::ser::Serialize;
+use serde_json::value::RawValue;
+
+use crate::{ensure_db_dir, format_record, get_db_path, load_db, options_preserving_format, save_locked, DBError, FileLock, JsonCodec, KeyValidator, BINARY_MAGIC, CHECKSUM_HEADER, DB, DEFAULT_LOCK_TIMEOUT, ENCRYPTED_MAGIC, GZIP_MAGIC, UTF8_BOM};
+
+/// Appends a single `key=value` record to the end of the file instead of rewriting it. Because
+/// later lines win on load, the appended value replaces any earlier one for the same key.
+///
+/// This trades file size for write speed: every append adds a line, so call `compact`
+/// periodically to fold superseded records away. Appends don't take backups. If the file was
+/// saved with checksums, the appended record gets one too. A file whose last line lacks its
+/// newline, as hand-edited files often do, has one added before the new record. Compressed,
+/// binary and encrypted databases can't be appended to.
+pub fn append_entry<T>(path: &str, key: &str, value: &T) -> Result<(), DBError> where T: Serialize {
+    KeyValidator::default().validate(key)?;
+    ensure_db_dir(path)?;
+
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let mut file = fs::OpenOptions::new().create(true).read(true).append(true).open(get_db_path(path))?;
+    let mut head: Vec<u8> = Vec::new();
+    let head_len: usize = (UTF8_BOM.len() + CHECKSUM_HEADER.len() + 1).max(ENCRYPTED_MAGIC.len());
+    (&mut file).take(head_len as u64).read_to_end(&mut head)?;
+    if head.starts_with(&GZIP_MAGIC) {
+        return Err(DBError::Unsupported("appending to a compressed database".to_string()));
+    }
+    if head.starts_with(BINARY_MAGIC) {
+        return Err(DBError::Unsupported("appending to a binary database".to_string()));
+    }
+    if head.starts_with(ENCRYPTED_MAGIC) {
+        return Err(DBError::WrongFormat(format!("{} is an encrypted database; appending would write plaintext", get_db_path(path))));
+    }
+    let first_lines: &[u8] = head.strip_prefix(UTF8_BOM).unwrap_or(&head);
+    let checksum = first_lines.starts_with(format!("{}\n", CHECKSUM_HEADER).as_bytes());
+    let mut record: String = String::new();
+    if !head.is_empty() {
+        let mut last: [u8; 1] = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            record.push('\n');
+        }
+    }
+    record.push_str(&format_record(&JsonCodec, key, value, checksum)?);
+    file.write_all(record.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Rewrites the file with one record per key, dropping values superseded by later appends.
+/// Values are copied through verbatim rather than re-encoded, and compression and checksums are
+/// preserved.
+pub fn compact(path: &str) -> Result<(), DBError> {
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let db: DB<Box<RawValue>> = load_db(path)?;
+    save_locked(path, &db, &options, &JsonCodec)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{load_db_binary, load_db_encrypted, save_db_binary, save_db_encrypted, save_db_with_options, SaveOptions};
+    use super::*;
+
+    #[test]
+    fn appended_values_override_earlier_ones() {
+        let path = "target/test_append_entries";
+
+        let _ = fs::remove_dir_all(path);
+
+        for i in 0..5 {
+            append_entry(path, "counter", &i).expect("appending should succeed");
+        }
+        append_entry(path, "other", &"x").expect("appending should succeed");
+
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert_eq!(contents.lines().count(), 6);
+        let loaded: DB<serde_json::Value> = load_db(path).expect("loading should succeed");
+        assert_eq!(loaded["counter"], 4);
+        assert_eq!(loaded["other"], "x");
+
+        compact(path).expect("compacting should succeed");
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        let compacted: DB<serde_json::Value> = load_db(path).expect("loading should succeed");
+        assert_eq!(compacted, loaded);
+    }
+
+    #[test]
+    fn appends_follow_hand_edited_files() {
+        let path = "target/test_append_hand_edited";
+
+        let _ = fs::remove_dir_all(path);
+        ensure_db_dir(path).unwrap();
+
+        fs::write(get_db_path(path), "a=1").unwrap();
+        append_entry(path, "b", &2).unwrap();
+        assert_eq!(fs::read_to_string(get_db_path(path)).unwrap(), "a=1\nb=2\n");
+        assert_eq!(load_db::<u32>(path).unwrap(), DB::from([("a".to_string(), 1), ("b".to_string(), 2)]));
+
+        save_db_with_options(path, &DB::from([("a".to_string(), 1)]), &SaveOptions { checksums: true, ..SaveOptions::default() }).unwrap();
+        let contents: String = fs::read_to_string(get_db_path(path)).unwrap();
+        fs::write(get_db_path(path), format!("\u{feff}{}", contents)).unwrap();
+        append_entry(path, "b", &2).unwrap();
+        let contents: String = fs::read_to_string(get_db_path(path)).unwrap();
+        assert!(contents.lines().last().unwrap().starts_with("b=2#"));
+        assert_eq!(load_db::<u32>(path).unwrap()["b"], 2);
+    }
+
+    #[test]
+    fn appending_to_an_encrypted_file_is_rejected() {
+        let path = "target/test_append_encrypted";
+        let key: [u8; 32] = [7; 32];
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = DB::from([("a".to_string(), 1)]);
+        save_db_encrypted(path, &db, &key).unwrap();
+        let before: Vec<u8> = fs::read(get_db_path(path)).unwrap();
+
+        assert!(matches!(append_entry(path, "b", &2), Err(DBError::WrongFormat(_))));
+        assert_eq!(fs::read(get_db_path(path)).unwrap(), before);
+        assert_eq!(load_db_encrypted::<u32>(path, &key).unwrap(), db);
+    }
+
+    #[test]
+    fn appending_to_a_binary_file_is_rejected() {
+        let path = "target/test_append_binary";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = DB::from([("a".to_string(), 1)]);
+        save_db_binary(path, &db).unwrap();
+        let before: Vec<u8> = fs::read(get_db_path(path)).unwrap();
+
+        assert!(matches!(append_entry(path, "b", &2), Err(DBError::Unsupported(_))));
+        assert_eq!(fs::read(get_db_path(path)).unwrap(), before);
+        assert_eq!(load_db_binary::<u32>(path).unwrap(), db);
+    }
+
+    #[test]
+    fn appending_to_a_compressed_file_is_rejected() {
+        let path = "target/test_append_compressed";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = DB::new();
+        db.insert("a".to_string(), 1);
+        save_db_with_options(path, &db, &SaveOptions { compress: true, ..SaveOptions::default() }).unwrap();
+
+        let err = append_entry(path, "b", &2).expect_err("appending should fail");
+        assert!(matches!(err, DBError::Unsupported(_)));
+        assert_eq!(load_db::<u32>(path).unwrap(), db);
+    }
+
+    #[test]
+    fn appends_to_a_checksummed_file_are_checksummed() {
+        let path = "target/test_append_checksummed";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = DB::new();
+        save_db_with_options(path, &db, &SaveOptions { checksums: true, ..SaveOptions::default() }).unwrap();
+        append_entry(path, "a", &1).unwrap();
+        append_entry(path, "a", &2).unwrap();
+        assert_eq!(load_db::<u32>(path).unwrap()["a"], 2);
+
+        compact(path).unwrap();
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert!(crate::has_checksums(&contents));
+        assert_eq!(contents.lines().count(), 2);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, FixedOffset};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+
+use crate::{decompress, ensure_db_dir, get_backup_dir, parse_backup_file_name, parse_db, read_backups, sync_dir, text_contents, DBError, FileLock, JsonCodec, DB, DEFAULT_LOCK_TIMEOUT};
+
+/// Bundles every backup under `path` into one gzipped tar file at `out_path`. An archive already
+/// at `out_path` keeps its entries, so archiving into the same file repeatedly accumulates
+/// backups. Entries keep their backup's file name, which is how `restore_from_archive` finds them
+/// by timestamp, and carry that timestamp as their modification time. The archive is written next
+/// to `out_path` and renamed into place, so it's never left half-written. With `remove_loose`,
+/// the archived backups are deleted once the archive is safely on disk.
+///
+/// Only the default `backups/` directory is archived; entries in it that aren't backups are left
+/// alone.
+pub fn archive_backups(path: &str, out_path: impl AsRef<Path>, remove_loose: bool) -> Result<(), DBError> {
+    ensure_db_dir(path)?;
+    // Held so a concurrent save can't prune a backup between archiving and removing it.
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let backup_dir = get_backup_dir(path);
+    let (backups, _) = read_backups(&backup_dir)?;
+
+    let out_path: &Path = out_path.as_ref();
+    let mut temp_name: OsString = out_path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path: PathBuf = PathBuf::from(temp_name);
+    if let Err(e) = write_archive(&temp_path, out_path, &backup_dir, &backups) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    fs::rename(&temp_path, out_path)?;
+    sync_dir(out_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")))?;
+
+    if remove_loose && !backups.is_empty() {
+        for (_, name) in &backups {
+            fs::remove_file(backup_dir.join(name))?;
+        }
+        sync_dir(&backup_dir)?;
+    }
+    Ok(())
+}
+
+// Writes `backups` and the entries of any existing archive at `out_path` to `temp_path`. A backup
+// that's both loose and already archived is written once, from the loose copy.
+fn write_archive(temp_path: &Path, out_path: &Path, backup_dir: &Path, backups: &[(DateTime<FixedOffset>, String)]) -> Result<(), DBError> {
+    let file = fs::File::create(temp_path)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    if out_path.exists() {
+        let loose: HashSet<&str> = backups.iter().map(|(_, name)| name.as_str()).collect();
+        let mut existing = tar::Archive::new(GzDecoder::new(fs::File::open(out_path)?));
+        for entry in existing.entries()? {
+            let mut entry = entry?;
+            let name: String = entry.path()?.to_string_lossy().into_owned();
+            if !loose.contains(name.as_str()) {
+                let mut header = entry.header().clone();
+                builder.append_data(&mut header, &name, &mut entry)?;
+            }
+        }
+    }
+    for (timestamp, name) in backups {
+        let contents: Vec<u8> = fs::read(backup_dir.join(name))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(timestamp.timestamp().max(0) as u64);
+        builder.append_data(&mut header, name, contents.as_slice())?;
+    }
+    let mut file = builder.into_inner()?.finish()?;
+    file.flush()?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Loads the backup taken at `timestamp` out of an archive written by `archive_backups`, parsing
+/// it the same way `load_db` parses the main file. Nothing is extracted to disk.
+pub fn restore_from_archive<T>(archive: impl AsRef<Path>, timestamp: &DateTime<FixedOffset>) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let archive: &Path = archive.as_ref();
+    let mut reader = tar::Archive::new(GzDecoder::new(fs::File::open(archive)?));
+    for entry in reader.entries()? {
+        let mut entry = entry?;
+        let name: String = entry.path()?.to_string_lossy().into_owned();
+        if parse_backup_file_name(&name).as_ref() != Some(timestamp) {
+            continue;
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        let contents: String = text_contents(decompress(bytes)?, &archive.join(&name))?;
+        return parse_db(&contents, false, &JsonCodec);
+    }
+    Err(DBError::NotFound(format!("backup {} in {}", timestamp, archive.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{list_backups, save_db, save_db_with_options, SaveOptions};
+
+    #[test]
+    fn archived_backups_restore_by_timestamp() {
+        let path = "target/test_archive";
+        let archive = "target/test_archive.tar.gz";
+
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_file(archive);
+
+        // Each save after the first backs up the previous generation, so backup `i` holds `i`.
+        for generation in 0..4 {
+            let db: DB<u32> = HashMap::from([("generation".to_string(), generation)]);
+            save_db(path, &db).unwrap();
+        }
+        // A compressed backup is archived as-is, and still restores.
+        let options = SaveOptions { compress: true, ..SaveOptions::default() };
+        save_db_with_options(path, &HashMap::from([("generation".to_string(), 4)]), &options).unwrap();
+        save_db(path, &HashMap::from([("generation".to_string(), 5)])).unwrap();
+        let timestamps = list_backups(path).unwrap();
+        assert_eq!(timestamps.len(), 5);
+
+        archive_backups(path, archive, false).unwrap();
+        assert_eq!(list_backups(path).unwrap(), timestamps);
+        archive_backups(path, archive, true).unwrap();
+        assert!(list_backups(path).unwrap().is_empty());
+
+        for (generation, timestamp) in timestamps.iter().enumerate() {
+            let restored: DB<u32> = restore_from_archive(archive, timestamp).unwrap();
+            assert_eq!(restored["generation"], generation as u32);
+        }
+        let missing = *timestamps.last().unwrap() + chrono::TimeDelta::seconds(1);
+        assert!(matches!(restore_from_archive::<u32>(archive, &missing), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn archiving_into_the_same_file_keeps_earlier_backups() {
+        let path = "target/test_archive_twice";
+        let archive = "target/test_archive_twice.tar.gz";
+
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_file(archive);
+
+        let mut timestamps: Vec<DateTime<FixedOffset>> = Vec::new();
+        for generation in 0..6 {
+            save_db(path, &HashMap::from([("generation".to_string(), generation)])).unwrap();
+            if generation % 3 == 2 {
+                timestamps.extend(list_backups(path).unwrap());
+                archive_backups(path, archive, true).unwrap();
+                assert!(list_backups(path).unwrap().is_empty());
+            }
+        }
+        assert_eq!(timestamps.len(), 5);
+        assert!(!fs::exists(format!("{}.tmp", archive)).unwrap());
+
+        let mut names: Vec<String> = Vec::new();
+        for entry in tar::Archive::new(GzDecoder::new(fs::File::open(archive).unwrap())).entries().unwrap() {
+            names.push(entry.unwrap().path().unwrap().to_string_lossy().into_owned());
+        }
+        assert_eq!(names.len(), timestamps.len());
+        for timestamp in &timestamps {
+            assert!(restore_from_archive::<u32>(archive, timestamp).is_ok());
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{DBError, SharedDatabase};
+
+enum Message {
+    Changed,
+    Stop,
+}
+
+/// Keeps a `SharedDatabase::enable_autosave` running. Dropping it, or calling `stop`, saves any
+/// change still waiting out the debounce, then stops the autosave thread.
+#[derive(Debug)]
+pub struct AutosaveHandle {
+    stop: Sender<Message>,
+    thread: Option<JoinHandle<Result<(), DBError>>>,
+}
+
+impl AutosaveHandle {
+    /// Stops autosaving, returning the outcome of the final save. Dropping the handle does the
+    /// same but ignores any error.
+    pub fn stop(mut self) -> Result<(), DBError> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), DBError> {
+        let _ = self.stop.send(Message::Stop);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(DBError::LockPoisoned),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AutosaveHandle {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+impl<T> SharedDatabase<T> where T: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Saves the database in the background once `debounce` has passed without a change, so a
+    /// burst of changes is written once, shortly after it ends.
+    ///
+    /// Changes are picked up through `Database::on_change`, so ones made through `entry` or
+    /// `merge_from` wait for the next reported change, or for autosave to stop. A failed save is
+    /// retried after the next change, and when autosave stops; only that final save's outcome is
+    /// reported, by `AutosaveHandle::stop`. Stopping also removes the listener autosave
+    /// registered, so autosave can be enabled again on the same database.
+    pub fn enable_autosave(&self, debounce: Duration) -> Result<AutosaveHandle, DBError> {
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let changes = sender.clone();
+        let listener: u64 = self.write()?.add_listener(Box::new(move |_| {
+            let _ = changes.send(Message::Changed);
+        }));
+
+        let db = self.clone();
+        let thread = thread::spawn(move || {
+            let result: Result<(), DBError> = run_autosave(&db, &receiver, debounce);
+            // The listener would otherwise outlive autosave, sending to a channel nobody reads.
+            if let Ok(mut db) = db.write() {
+                db.remove_listener(listener);
+            }
+            result
+        });
+        Ok(AutosaveHandle { stop: sender, thread: Some(thread) })
+    }
+}
+
+// The autosave thread's loop, returning the outcome of the final save.
+fn run_autosave<T>(db: &SharedDatabase<T>, receiver: &Receiver<Message>, debounce: Duration) -> Result<(), DBError> where T: Serialize + DeserializeOwned {
+    while let Ok(Message::Changed) = receiver.recv() {
+        loop {
+            match receiver.recv_timeout(debounce) {
+                Ok(Message::Changed) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => return db.save(),
+            }
+        }
+        let _ = db.save();
+    }
+    // Stopped between bursts: there may still be changes the listener never reported.
+    db.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+    use crate::{list_backups, load_db};
+
+    #[test]
+    fn bursts_are_saved_once_after_the_debounce() {
+        let path = "target/test_autosave_debounce";
+
+        let _ = fs::remove_dir_all(path);
+
+        let debounce = Duration::from_millis(100);
+        let db: SharedDatabase<u32> = SharedDatabase::open(path).unwrap();
+        let handle = db.enable_autosave(debounce).unwrap();
+        for i in 0..20 {
+            db.insert(format!("key{}", i), i).unwrap();
+        }
+        assert!(load_db::<u32>(path).unwrap().is_empty());
+
+        thread::sleep(debounce * 4);
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 20);
+        // The first save has nothing to back up and every later write of new contents takes a
+        // backup, so no backup means one save.
+        assert!(list_backups(path).unwrap().is_empty());
+
+        // Stopping right after a change, well within the debounce, still saves it.
+        db.insert("last", 20).unwrap();
+        handle.stop().unwrap();
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 21);
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn stopping_unregisters_the_listener() {
+        let path = "target/test_autosave_restart";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: SharedDatabase<u32> = SharedDatabase::open(path).unwrap();
+        for i in 0..3 {
+            let handle = db.enable_autosave(Duration::from_millis(10)).unwrap();
+            assert_eq!(db.read().unwrap().listener_count(), 1);
+            db.insert(format!("key{}", i), i).unwrap();
+            handle.stop().unwrap();
+            assert_eq!(db.read().unwrap().listener_count(), 0);
+        }
+        drop(db.enable_autosave(Duration::from_millis(10)).unwrap());
+        assert_eq!(db.read().unwrap().listener_count(), 0);
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 3);
+    }
+}
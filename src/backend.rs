@@ -0,0 +1,145 @@
+use std::sync::{Mutex, PoisonError};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{commit, ensure_db_dir, parse_db, read_db_file, records, validate_keys, write_records, DBError, FileLock, JsonCodec, SaveOptions, DB};
+
+/// Where `save_db_to` and `load_db_from` keep a database's text, so the format can be used
+/// without the filesystem. Implementations only move whole files' worth of text around; parsing
+/// and formatting stay in the crate.
+pub trait Backend {
+    /// The stored text, or an empty string if nothing has been stored yet.
+    fn read_all(&self) -> Result<String, DBError>;
+
+    /// Replaces the stored text with `data`.
+    fn write_all(&self, data: &str) -> Result<(), DBError>;
+
+    /// Called by `save_db_to` right before `write_all`, to keep a copy of the text about to be
+    /// replaced. Does nothing by default.
+    fn backup(&self) -> Result<(), DBError> {
+        Ok(())
+    }
+}
+
+/// The database directory at `path`, read and written exactly as `load_db` and `save_db` do,
+/// including locking, atomic replacement and rotating backups, which `write_all` takes itself.
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    path: String,
+}
+
+impl FileBackend {
+    pub fn new(path: impl Into<String>) -> Self {
+        FileBackend { path: path.into() }
+    }
+}
+
+impl Backend for FileBackend {
+    fn read_all(&self) -> Result<String, DBError> {
+        read_db_file(&self.path)
+    }
+
+    fn write_all(&self, data: &str) -> Result<(), DBError> {
+        let options = SaveOptions::default();
+        ensure_db_dir(&self.path)?;
+        let _lock = FileLock::acquire(&self.path, options.lock_timeout)?;
+        commit(&self.path, &options, |writer| {
+            writer.write_all(data.as_bytes())?;
+            Ok(records(data, true).count())
+        })
+    }
+}
+
+/// Keeps the database in memory, for tests and data that doesn't need to outlive the process.
+/// Every save keeps the text it replaced, available from `backups`.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    data: Mutex<String>,
+    backups: Mutex<Vec<String>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend::default()
+    }
+
+    /// The text replaced by each save so far, oldest first.
+    pub fn backups(&self) -> Vec<String> {
+        self.backups.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn read_all(&self) -> Result<String, DBError> {
+        Ok(self.data.lock().map_err(|_| DBError::LockPoisoned)?.clone())
+    }
+
+    fn write_all(&self, data: &str) -> Result<(), DBError> {
+        *self.data.lock().map_err(|_| DBError::LockPoisoned)? = data.to_string();
+        Ok(())
+    }
+
+    fn backup(&self) -> Result<(), DBError> {
+        let current: String = self.read_all()?;
+        self.backups.lock().map_err(|_| DBError::LockPoisoned)?.push(current);
+        Ok(())
+    }
+}
+
+/// Like `load_db`, reading from `backend`.
+pub fn load_db_from<T, B>(backend: &B) -> Result<DB<T>, DBError> where T: DeserializeOwned, B: Backend + ?Sized {
+    parse_db(&backend.read_all()?, false, &JsonCodec)
+}
+
+/// Like `save_db`, writing to `backend`.
+pub fn save_db_to<T, B>(backend: &B, contents: &DB<T>) -> Result<(), DBError> where T: Serialize, B: Backend + ?Sized {
+    let options = SaveOptions::default();
+    validate_keys(contents, &options)?;
+    let mut data: Vec<u8> = Vec::new();
+    write_records(&mut data, contents, &options, &JsonCodec)?;
+    let data = String::from_utf8(data).map_err(|e| DBError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+    backend.backup()?;
+    backend.write_all(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::{list_backups, load_db};
+
+    #[test]
+    fn memory_backend_round_trip() {
+        let backend = MemoryBackend::new();
+        assert!(load_db_from::<u32, _>(&backend).unwrap().is_empty());
+
+        let mut db: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        save_db_to(&backend, &db).unwrap();
+        assert_eq!(load_db_from::<u32, _>(&backend).unwrap(), db);
+
+        db.remove("a");
+        save_db_to(&backend, &db).unwrap();
+        assert_eq!(load_db_from::<u32, _>(&backend).unwrap(), db);
+        let backups = backend.backups();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0], "");
+        assert_eq!(parse_db::<u32, _>(&backups[1], false, &JsonCodec).unwrap().len(), 2);
+        assert!(matches!(save_db_to(&backend, &HashMap::from([(String::new(), 0)])), Err(DBError::InvalidKey { .. })));
+    }
+
+    #[test]
+    fn file_backend_matches_save_db() {
+        let path = "target/test_backend_file";
+
+        let _ = fs::remove_dir_all(path);
+
+        let backend: Box<dyn Backend> = Box::new(FileBackend::new(path));
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1)]);
+        save_db_to(backend.as_ref(), &db).unwrap();
+        save_db_to(backend.as_ref(), &HashMap::from([("a".to_string(), 2)])).unwrap();
+        assert_eq!(load_db::<u32>(path).unwrap()["a"], 2);
+        assert_eq!(load_db_from::<u32, _>(backend.as_ref()).unwrap()["a"], 2);
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::json_schema::validate_values;
+use crate::{commit, ensure_db_dir, get_db_path, read_file_bytes, validate_keys, DBError, FileLock, SaveOptions, DB, BINARY_MAGIC};
+
+/// Saves the whole map in one bincode blob instead of `key=value` lines. This is much faster to
+/// read and write for large databases, but the file isn't human-readable and `T` must not rely on
+/// self-describing formats (e.g. `serde_json::Value` or `#[serde(untagged)]` won't load back).
+///
+/// Backups, locking and atomicity work exactly as for `save_db`.
+pub fn save_db_binary<T>(path: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize {
+    save_binary_with_options(path, contents, &SaveOptions::default())
+}
+
+// `SaveOptions::sorted`, `canonical` and `checksums` only apply to text records and are ignored here.
+pub(crate) fn save_binary_with_options<T>(path: &str, contents: &DB<T>, options: &SaveOptions) -> Result<(), DBError> where T: Serialize {
+    validate_keys(contents, options)?;
+    validate_values(contents, options.json_schema.as_ref())?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, options.lock_timeout)?;
+    commit(path, options, |writer| {
+        writer.write_all(BINARY_MAGIC)?;
+        bincode::serialize_into(writer, contents)?;
+        Ok(contents.len())
+    })
+}
+
+/// Loads a database written by `save_db_binary`. A missing file loads as an empty database; a text
+/// database is rejected rather than misread.
+pub fn load_db_binary<T>(path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let file_path = get_db_path(path);
+    let bytes = match read_file_bytes(Path::new(&file_path)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(DB::new()),
+        Err(e) => return Err(e.into()),
+    };
+    match bytes.strip_prefix(BINARY_MAGIC) {
+        Some(payload) => Ok(bincode::deserialize(payload)?),
+        None => Err(DBError::WrongFormat(format!("{} is not a binary database; use load_db", file_path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use serde::Deserialize;
+    use crate::{list_backups, load_db, save_db};
+
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        values: Vec<f64>,
+    }
+
+    #[test]
+    fn binary_round_trip_of_a_few_thousand_entries() {
+        let path = "target/test_binary_round_trip";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(load_db_binary::<Reading>(path).unwrap().is_empty());
+
+        let db: DB<Reading> = (0..5000).map(|i| {
+            (format!("reading:{}", i), Reading { sensor: format!("s{}", i % 7), values: vec![i as f64, 0.5] })
+        }).collect();
+        save_db_binary(path, &DB::<Reading>::new()).expect("saving should succeed");
+        save_db_binary(path, &db).expect("saving should succeed");
+        save_db_binary(path, &db).expect("saving should succeed");
+
+        let loaded: DB<Reading> = load_db_binary(path).expect("loading should succeed");
+        assert_eq!(loaded, db);
+        // Identical binary saves don't churn backups either.
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn formats_are_not_confused() {
+        let binary_path = "target/test_binary_detect_binary";
+        let text_path = "target/test_binary_detect_text";
+
+        let _ = fs::remove_dir_all(binary_path);
+        let _ = fs::remove_dir_all(text_path);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        save_db_binary(binary_path, &db).unwrap();
+        save_db(text_path, &db).unwrap();
+
+        assert!(matches!(load_db::<u32>(binary_path).unwrap_err(), DBError::WrongFormat(_)));
+        assert!(matches!(load_db_binary::<u32>(text_path).unwrap_err(), DBError::WrongFormat(_)));
+    }
+}
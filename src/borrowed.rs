@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use serde::Deserialize;
+
+use crate::{decode_key, has_checksums, is_pretty, logical_lines, split_record, DBError};
+
+/// Parses `contents`, the text of a data file, without copying keys or values out of it where it
+/// can. The returned map borrows from `contents`, so the buffer has to outlive it: read the file
+/// into a `String` the caller keeps, parse, and drop the map before the string.
+///
+/// Keys are borrowed unless they had to be percent-escaped in the file, in which case they're
+/// decoded into an owned `Cow`. Values are deserialized with `serde_json`'s borrowing
+/// deserializer, so `T` can hold `&'a str` slices of the buffer. A JSON string containing an
+/// escape such as `\n` or `\"` can't be borrowed as `&str` and fails to load. Where that can
+/// happen, use a `Cow<'a, str>` field marked `#[serde(borrow)]`, which borrows unescaped strings
+/// and owns the rest; serde never borrows into a `Cow` without that attribute, so a bare
+/// `Cow<'a, str>` value is always an owned copy. Compressed data isn't detected.
+pub fn load_db_borrowed<'a, T>(contents: &'a str) -> Result<HashMap<Cow<'a, str>, T>, DBError> where T: Deserialize<'a> {
+    let contents: &'a str = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    let checksummed: bool = has_checksums(contents);
+    let mut db: HashMap<Cow<'a, str>, T> = HashMap::new();
+    for (index, line) in logical_lines(contents, is_pretty(contents.lines())) {
+        let Some(record) = split_record(index, line, false, checksummed, "=") else { continue };
+        let (raw_key, value) = record?;
+        let key: Cow<'a, str> = if raw_key.contains('%') { Cow::Owned(decode_key(raw_key)?) } else { Cow::Borrowed(raw_key) };
+        db.insert(key, serde_json::from_str(value)?);
+    }
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{get_db_path, save_db_with_options, SaveOptions, DB};
+
+    // Whether `slice` lies inside `buffer`, i.e. was borrowed rather than copied.
+    fn borrows_from(slice: &str, buffer: &str) -> bool {
+        buffer.as_bytes().as_ptr_range().contains(&slice.as_ptr())
+    }
+
+    #[test]
+    fn str_values_borrow_from_the_buffer() {
+        let path = "target/test_borrowed";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<String> = DB::from([("plain".to_string(), "value".to_string()), ("with=sign".to_string(), "other".to_string())]);
+        save_db_with_options(path, &db, &SaveOptions { checksums: true, ..SaveOptions::default() }).unwrap();
+        let contents: String = fs::read_to_string(get_db_path(path)).unwrap();
+
+        let loaded: HashMap<Cow<str>, &str> = load_db_borrowed(&contents).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.values().all(|value| borrows_from(value, &contents)));
+        assert_eq!(loaded["plain"], "value");
+        let (key, _) = loaded.get_key_value("plain").unwrap();
+        assert!(matches!(key, Cow::Borrowed(key) if borrows_from(key, &contents)));
+        // An escaped key has to be decoded into a string of its own.
+        assert!(matches!(loaded.get_key_value("with=sign"), Some((Cow::Owned(_), &"other"))));
+    }
+
+    #[test]
+    fn escaped_values_need_cow() {
+        let contents: &str = "a=\"line\\nbreak\"\n";
+        assert!(matches!(load_db_borrowed::<&str>(contents), Err(DBError::Serde(_))));
+        let loaded: HashMap<Cow<str>, Cow<str>> = load_db_borrowed(contents).unwrap();
+        assert_eq!(loaded["a"], "line\nbreak");
+        // Without `#[serde(borrow)]`, even an unescaped string is copied.
+        let loaded: HashMap<Cow<str>, Cow<str>> = load_db_borrowed("a=\"plain\"\n").unwrap();
+        assert!(matches!(loaded["a"], Cow::Owned(_)));
+    }
+
+    #[test]
+    fn borrowed_cow_fields_borrow_unless_escaped() {
+        #[derive(Deserialize)]
+        struct Note<'a> {
+            #[serde(borrow)]
+            text: Cow<'a, str>,
+        }
+
+        let contents: &str = "plain={\"text\":\"value\"}\nescaped={\"text\":\"line\\nbreak\"}\n";
+        let loaded: HashMap<Cow<str>, Note> = load_db_borrowed(contents).unwrap();
+        assert!(matches!(&loaded["plain"].text, Cow::Borrowed(text) if borrows_from(text, contents)));
+        assert!(matches!(&loaded["escaped"].text, Cow::Owned(text) if text == "line\nbreak"));
+    }
+}
@@ -0,0 +1,167 @@
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::DBError;
+
+/// Turns values into the text stored after the `=` on each line, and back. Encoded values must
+/// not contain line breaks, or the record separator if one is configured; saving rejects any
+/// that do.
+pub trait Codec {
+    fn encode<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize;
+    fn decode<T>(&self, encoded: &str) -> Result<T, DBError> where T: DeserializeOwned;
+
+    /// The encoding used with `SaveOptions::pretty`, which may span several lines as long as
+    /// `decode` ignores the indentation added to all but the first. Defaults to `encode`.
+    fn encode_pretty<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize {
+        self.encode(value)
+    }
+}
+
+/// Compact single-line JSON, the format used by `save_db` and `load_db`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize {
+        Ok(serde_json::to_string(value)?)
+    }
+
+    fn decode<T>(&self, encoded: &str) -> Result<T, DBError> where T: DeserializeOwned {
+        Ok(serde_json::from_str(encoded)?)
+    }
+
+    fn encode_pretty<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize {
+        Ok(serde_json::to_string_pretty(value)?)
+    }
+}
+
+/// Values as TOML inline values: `{ name = "a", tags = ["x"] }` for a struct. These fit on one
+/// line, so this is safe with the default record separator, except that the `toml` crate writes
+/// strings containing a line break as multi-line strings; saving rejects those values unless a
+/// `DbConfig::record_separator` is set. TOML has no null, so `None` fields are left out and
+/// `Option` values at the top level can't be encoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TomlCodec;
+
+impl Codec for TomlCodec {
+    fn encode<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize {
+        let mut encoded: String = String::new();
+        value.serialize(toml::ser::ValueSerializer::new(&mut encoded)).map_err(|e| DBError::Toml(e.to_string()))?;
+        Ok(encoded)
+    }
+
+    fn decode<T>(&self, encoded: &str) -> Result<T, DBError> where T: DeserializeOwned {
+        let deserializer = toml::de::ValueDeserializer::parse(encoded).map_err(|e| DBError::Toml(e.to_string()))?;
+        T::deserialize(deserializer).map_err(|e| DBError::Toml(e.to_string()))
+    }
+}
+
+/// Values as YAML documents. Anything but a scalar is written in block style, one line per
+/// field, so this is *not* safe with the default record separator: saving rejects such values.
+/// Pair it with a `DbConfig::record_separator` that can't appear in the YAML, such as `"\x1e"`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YamlCodec;
+
+impl Codec for YamlCodec {
+    fn encode<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize {
+        Ok(serde_yaml::to_string(value)?)
+    }
+
+    fn decode<T>(&self, encoded: &str) -> Result<T, DBError> where T: DeserializeOwned {
+        Ok(serde_yaml::from_str(encoded)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::{get_db_path, load_db_with, load_db_with_codec, save_db_with, save_db_with_codec, DbConfig, DB};
+
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Service {
+        name: String,
+        port: u16,
+        tags: Vec<String>,
+        owner: Option<String>,
+    }
+
+    fn services() -> DB<Service> {
+        HashMap::from([
+            ("web".to_string(), Service { name: "web".to_string(), port: 80, tags: vec!["public".to_string()], owner: Some("ops".to_string()) }),
+            ("db".to_string(), Service { name: "postgres".to_string(), port: 5432, tags: vec![], owner: None }),
+        ])
+    }
+
+    // Stores values as JSON reversed, which is line-safe but unreadable as plain JSON.
+    struct ReversedJson;
+
+    impl Codec for ReversedJson {
+        fn encode<T>(&self, value: &T) -> Result<String, DBError> where T: Serialize {
+            Ok(serde_json::to_string(value)?.chars().rev().collect())
+        }
+
+        fn decode<T>(&self, encoded: &str) -> Result<T, DBError> where T: DeserializeOwned {
+            let json: String = encoded.chars().rev().collect();
+            Ok(serde_json::from_str(&json)?)
+        }
+    }
+
+    #[test]
+    fn custom_codec_round_trips() {
+        let path = "target/test_codec_custom";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<Vec<u32>> = HashMap::new();
+        db.insert("list".to_string(), vec![1, 2, 3]);
+        save_db_with_codec(path, &db, &ReversedJson).expect("saving should succeed");
+
+        assert_eq!(fs::read_to_string(get_db_path(path)).unwrap(), "list=]3,2,1[\n");
+        let loaded: DB<Vec<u32>> = load_db_with_codec(path, &ReversedJson).expect("loading should succeed");
+        assert_eq!(loaded, db);
+        assert!(load_db_with_codec::<Vec<u32>, _>(path, &JsonCodec).is_err());
+    }
+
+    #[test]
+    fn toml_codec_round_trips_on_single_lines() {
+        let path = "target/test_codec_toml";
+
+        let _ = fs::remove_dir_all(path);
+
+        save_db_with_codec(path, &services(), &TomlCodec).expect("saving should succeed");
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.contains("db={ name = \"postgres\", port = 5432, tags = [] }\n"));
+
+        let loaded: DB<Service> = load_db_with_codec(path, &TomlCodec).expect("loading should succeed");
+        assert_eq!(loaded, services());
+
+        let mut db: DB<Service> = services();
+        db.get_mut("db").unwrap().name = "two\nlines".to_string();
+        let err = save_db_with_codec(path, &db, &TomlCodec).unwrap_err();
+        assert!(matches!(err, DBError::InvalidValue { .. }));
+    }
+
+    #[test]
+    fn yaml_codec_round_trips_with_a_record_separator() {
+        let path = "target/test_codec_yaml";
+
+        let _ = fs::remove_dir_all(path);
+
+        // Block-style YAML spans lines, which the default format can't hold.
+        let err = save_db_with_codec(path, &services(), &YamlCodec).unwrap_err();
+        assert!(matches!(err, DBError::InvalidValue { .. }));
+
+        let config = DbConfig::new().codec(YamlCodec).record_separator("\x1e").build().unwrap();
+        let mut db: DB<Service> = services();
+        db.get_mut("web").unwrap().name = "two\nlines".to_string();
+        save_db_with(path, &db, &config).expect("saving should succeed");
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert!(contents.contains("db=name: postgres\nport: 5432\n"));
+
+        let loaded: DB<Service> = load_db_with(path, &config).expect("loading should succeed");
+        assert_eq!(loaded, db);
+    }
+}
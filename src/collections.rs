@@ -0,0 +1,126 @@
+use std::collections::{BTreeSet, HashMap};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{commit, decode_key, encode_key, encode_value, ensure_db_dir, format_line, options_preserving_format, read_db_file, records, write_headers, DBError, FileLock, JsonCodec, KeyValidator, DB, DEFAULT_LOCK_TIMEOUT};
+
+// Collections share the data file with plain records: a record belongs to collection `ns` when
+// its key is written as `ns/key`. Both halves are percent-encoded with `/` escaped too, so the
+// first raw `/` is always the separator. A plain key saved with `save_db` that contains a `/`
+// therefore reads as a collection entry as well.
+
+/// Replaces the entries of collection `namespace` with `contents`, leaving every other record
+/// in the file, namespaced or not, as it was.
+pub fn save_collection<T>(path: &str, namespace: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize {
+    let validator = KeyValidator::default();
+    validator.validate(namespace)?;
+    let mut lines: Vec<(String, String)> = Vec::with_capacity(contents.len());
+    for (key, value) in contents {
+        validator.validate(key)?;
+        lines.push((format!("{}/{}", encode_segment(namespace), encode_segment(key)), encode_value(&JsonCodec, key, value)?));
+    }
+
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let existing = read_db_file(path)?;
+    for record in records(&existing, false) {
+        let record = record?;
+        if split_key(record.raw_key)?.is_none_or(|(ns, _)| ns != namespace) {
+            lines.push((record.raw_key.to_string(), record.value.to_string()));
+        }
+    }
+    commit(path, &options, |writer| {
+        write_headers(writer, &options)?;
+        for (raw_key, value) in &lines {
+            writer.write_all(format_line(raw_key, value, options.checksums).as_bytes())?;
+        }
+        Ok(lines.len())
+    })
+}
+
+/// Loads only the entries of collection `namespace`, with the namespace stripped from their keys.
+pub fn load_collection<T>(path: &str, namespace: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let contents = read_db_file(path)?;
+    let mut raw: HashMap<String, &str> = HashMap::new();
+    for record in records(&contents, false) {
+        let record = record?;
+        if let Some((ns, key)) = split_key(record.raw_key)? && ns == namespace {
+            raw.insert(key, record.value);
+        }
+    }
+    raw.into_iter().map(|(key, value)| Ok((key, serde_json::from_str(value)?))).collect()
+}
+
+/// The names of all collections in the file, sorted.
+pub fn list_collections(path: &str) -> Result<Vec<String>, DBError> {
+    let contents = read_db_file(path)?;
+    let mut namespaces: BTreeSet<String> = BTreeSet::new();
+    for record in records(&contents, false) {
+        if let Some((ns, _)) = split_key(record?.raw_key)? {
+            namespaces.insert(ns);
+        }
+    }
+    Ok(namespaces.into_iter().collect())
+}
+
+fn encode_segment(segment: &str) -> String {
+    // `encode_key` leaves `/` alone and never produces one, so this can't clash with its escapes.
+    encode_key(segment).replace('/', "%2F")
+}
+
+// Splits a raw key into its decoded namespace and key, or `None` for a plain key.
+fn split_key(raw_key: &str) -> Result<Option<(String, String)>, DBError> {
+    match raw_key.split_once('/') {
+        Some((ns, key)) => Ok(Some((decode_key(ns)?, decode_key(key)?))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{load_db, save_db};
+
+    #[test]
+    fn collections_are_isolated() {
+        let path = "target/test_collections";
+
+        let _ = fs::remove_dir_all(path);
+        save_db(path, &HashMap::from([("plain".to_string(), 0)])).unwrap();
+
+        let users: DB<u32> = HashMap::from([("alice".to_string(), 1), ("a/b".to_string(), 2)]);
+        let orders: DB<u32> = HashMap::from([("alice".to_string(), 10)]);
+        save_collection(path, "users", &users).unwrap();
+        save_collection(path, "orders", &orders).unwrap();
+
+        assert_eq!(load_collection::<u32>(path, "users").unwrap(), users);
+        assert_eq!(load_collection::<u32>(path, "orders").unwrap(), orders);
+        assert!(load_collection::<u32>(path, "missing").unwrap().is_empty());
+        assert_eq!(list_collections(path).unwrap(), ["orders", "users"]);
+        assert_eq!(load_db::<u32>(path).unwrap()["plain"], 0);
+
+        // A `/` in a key is escaped, so it doesn't start a nested namespace.
+        let contents = fs::read_to_string(crate::get_db_path(path)).unwrap();
+        assert!(contents.contains("users/a%2Fb=2"));
+
+        // Replacing one collection leaves the other alone.
+        save_collection(path, "users", &HashMap::from([("bob".to_string(), 3)])).unwrap();
+        assert_eq!(load_collection::<u32>(path, "users").unwrap(), HashMap::from([("bob".to_string(), 3)]));
+        assert_eq!(load_collection::<u32>(path, "orders").unwrap(), orders);
+    }
+
+    #[test]
+    fn namespaces_with_slashes_round_trip() {
+        let path = "target/test_collections_slash_namespace";
+
+        let _ = fs::remove_dir_all(path);
+
+        save_collection(path, "team/a", &HashMap::from([("k".to_string(), 1)])).unwrap();
+        save_collection(path, "team", &HashMap::from([("a/k".to_string(), 2)])).unwrap();
+        assert_eq!(load_collection::<u32>(path, "team/a").unwrap()["k"], 1);
+        assert_eq!(load_collection::<u32>(path, "team").unwrap()["a/k"], 2);
+        assert_eq!(list_collections(path).unwrap(), ["team", "team/a"]);
+    }
+}
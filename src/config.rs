@@ -0,0 +1,238 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::binary::save_binary_with_options;
+use crate::separators::load_separated;
+use crate::{load_db_binary, load_db_with_codec, save_with, BackupMode, BackupPolicy, Codec, DBError, JsonCodec, JsonSchema, KeyValidator, SaveOptions, DB};
+
+/// Everything that controls how a database is saved and loaded, assembled builder-style, e.g.
+/// `DbConfig::new().max_backups(3).compress(true).build()?`. `build` rejects option combinations
+/// that can't work together. `DbConfig::new()` matches what `save_db` and `load_db` do.
+#[derive(Debug, Clone, Default)]
+pub struct DbConfig<C = JsonCodec> {
+    options: SaveOptions,
+    binary: bool,
+    codec: C,
+    // Whether `codec` was called, since binary files are bincode whatever the codec.
+    custom_codec: bool,
+}
+
+impl DbConfig {
+    pub fn new() -> Self {
+        DbConfig::default()
+    }
+}
+
+impl<C> DbConfig<C> where C: Codec {
+    pub fn max_backups(self, count: usize) -> Self {
+        self.backup_policy(BackupPolicy::KeepCount(count))
+    }
+
+    pub fn backup_policy(mut self, policy: BackupPolicy) -> Self {
+        self.options.backup_policy = policy;
+        self
+    }
+
+    pub fn backup_mode(mut self, mode: BackupMode) -> Self {
+        self.options.backup = mode;
+        self
+    }
+
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.options.fsync = fsync;
+        self
+    }
+
+    pub fn backup_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.backup_dir = Some(dir.into());
+        self
+    }
+
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.options.compress = compress;
+        self
+    }
+
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.options.lock_timeout = timeout;
+        self
+    }
+
+    pub fn force_backup(mut self, force: bool) -> Self {
+        self.options.force_backup = force;
+        self
+    }
+
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.options.sorted = sorted;
+        self
+    }
+
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.options.canonical = canonical;
+        self
+    }
+
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.options.checksums = checksums;
+        self
+    }
+
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.options.pretty = pretty;
+        self
+    }
+
+    pub fn record_separator(mut self, separator: impl Into<String>) -> Self {
+        self.options.record_separator = separator.into();
+        self
+    }
+
+    pub fn kv_separator(mut self, separator: impl Into<String>) -> Self {
+        self.options.kv_separator = separator.into();
+        self
+    }
+
+    pub fn schema_version(mut self, version: u32) -> Self {
+        self.options.schema_version = Some(version);
+        self
+    }
+
+    pub fn key_validator(mut self, validator: KeyValidator) -> Self {
+        self.options.key_validator = validator;
+        self
+    }
+
+    pub fn json_schema(mut self, schema: JsonSchema) -> Self {
+        self.options.json_schema = Some(schema);
+        self
+    }
+
+    pub fn clock(mut self, clock: fn() -> DateTime<FixedOffset>) -> Self {
+        self.options.clock = clock;
+        self
+    }
+
+    /// Save the whole map as one bincode blob, as `save_db_binary` does.
+    pub fn binary(mut self, binary: bool) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    /// Encode values with `codec` instead of JSON. Can't be combined with `binary`.
+    pub fn codec<D>(self, codec: D) -> DbConfig<D> where D: Codec {
+        DbConfig { options: self.options, binary: self.binary, codec, custom_codec: true }
+    }
+
+    /// Checks that the chosen options are compatible.
+    pub fn build(self) -> Result<Self, DBError> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    fn validate(&self) -> Result<(), DBError> {
+        self.options.check_separators()?;
+        // Binary files have no lines, so line-level options would be silently ignored.
+        if self.binary && self.options.checksums {
+            return Err(DBError::InvalidConfig("checksums can't be combined with binary".to_string()));
+        }
+        if self.binary && self.options.sorted {
+            return Err(DBError::InvalidConfig("sorted can't be combined with binary".to_string()));
+        }
+        if self.binary && self.options.canonical {
+            return Err(DBError::InvalidConfig("canonical can't be combined with binary".to_string()));
+        }
+        if self.binary && self.options.pretty {
+            return Err(DBError::InvalidConfig("pretty can't be combined with binary".to_string()));
+        }
+        if self.binary && self.options.schema_version.is_some() {
+            return Err(DBError::InvalidConfig("schema_version can't be combined with binary".to_string()));
+        }
+        if self.binary && self.custom_codec {
+            return Err(DBError::InvalidConfig("a codec can't be combined with binary".to_string()));
+        }
+        if self.binary && self.options.has_custom_separators() {
+            return Err(DBError::InvalidConfig("custom separators can't be combined with binary".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Saves `contents` as configured by `config`.
+pub fn save_db_with<T, C>(path: &str, contents: &DB<T>, config: &DbConfig<C>) -> Result<(), DBError> where T: Serialize, C: Codec {
+    config.validate()?;
+    if config.binary {
+        return save_binary_with_options(path, contents, &config.options);
+    }
+    save_with(path, contents, &config.options, &config.codec)
+}
+
+/// Loads a database saved with `save_db_with` and the same `config`.
+pub fn load_db_with<T, C>(path: &str, config: &DbConfig<C>) -> Result<DB<T>, DBError> where T: DeserializeOwned, C: Codec {
+    config.validate()?;
+    if config.binary {
+        return load_db_binary(path);
+    }
+    if config.options.has_custom_separators() {
+        return load_separated(path, &config.options, &config.codec);
+    }
+    load_db_with_codec(path, &config.codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use crate::{get_db_path, list_backups_in, TomlCodec, GZIP_MAGIC};
+
+    #[test]
+    fn configured_round_trip() {
+        let path = "target/test_config_round_trip";
+        let backups = "target/test_config_round_trip_backups";
+
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_dir_all(backups);
+
+        let config = DbConfig::new().max_backups(2).compress(true).backup_dir(backups).fsync(false).build().unwrap();
+        let mut db: DB<String> = HashMap::new();
+        for i in 0..4 {
+            db.insert(format!("key{}", i), format!("value{}", i));
+            save_db_with(path, &db, &config).expect("saving should succeed");
+        }
+
+        assert_eq!(load_db_with::<String, _>(path, &config).unwrap(), db);
+        assert!(fs::read(get_db_path(path)).unwrap().starts_with(&GZIP_MAGIC));
+        assert_eq!(list_backups_in(Path::new(backups)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let path = "target/test_config_binary";
+
+        let _ = fs::remove_dir_all(path);
+
+        let config = DbConfig::new().binary(true).build().unwrap();
+        let mut db: DB<Vec<u8>> = HashMap::new();
+        db.insert("bytes".to_string(), vec![1, 2, 3]);
+        save_db_with(path, &db, &config).unwrap();
+
+        assert_eq!(load_db_with::<Vec<u8>, _>(path, &config).unwrap(), db);
+        assert!(matches!(load_db_with::<Vec<u8>, _>(path, &DbConfig::new()), Err(DBError::WrongFormat(_))));
+    }
+
+    #[test]
+    fn conflicting_options_are_rejected() {
+        let err = DbConfig::new().binary(true).checksums(true).build().unwrap_err();
+        assert!(matches!(err, DBError::InvalidConfig(_)));
+        assert!(matches!(DbConfig::new().binary(true).sorted(true).build(), Err(DBError::InvalidConfig(_))));
+        assert!(matches!(DbConfig::new().binary(true).schema_version(2).build(), Err(DBError::InvalidConfig(_))));
+        assert!(matches!(DbConfig::new().binary(true).codec(TomlCodec).build(), Err(DBError::InvalidConfig(_))));
+        assert!(matches!(DbConfig::new().codec(TomlCodec).binary(true).build(), Err(DBError::InvalidConfig(_))));
+        assert!(DbConfig::new().codec(TomlCodec).build().is_ok());
+    }
+}
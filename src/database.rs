@@ -0,0 +1,1306 @@
+use std::collections::{hash_map, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use chrono::{DateTime, FixedOffset};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::merge::merge_changed;
+use crate::metrics::Metrics;
+use crate::modified::{self, load_tracked, stamp, ModifiedTimes};
+use crate::{decode_key, encode_key, ensure_db_dir, get_db_path, get_wal_path, rename_entry, save_with_reporting, DBError, JsonCodec, JsonSchema, KeyValidator, MergeStrategy, MetricsSnapshot, SaveOptions, SaveReport, DB};
+
+/// Options for `Database::open_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseOptions {
+    /// Save unsaved changes when the handle is dropped. Errors during that save are ignored, so
+    /// call `save` explicitly where failures matter.
+    pub auto_save_on_drop: bool,
+    /// Checked by `insert`, `extend` and `rename`, and again for every key on `save`.
+    pub key_validator: KeyValidator,
+    /// Treat keys that differ only in case as the same key. Keys are compared after
+    /// `str::to_lowercase`, i.e. Unicode lowercasing rather than full case folding (so `"ß"` and
+    /// `"SS"` stay distinct). The casing of the latest insert is what's kept and saved; if the
+    /// file already holds keys that collide, which one wins is unspecified.
+    pub case_insensitive: bool,
+    /// Log every `insert`, `remove`, `rename` and `clear` to `<db_dir>/memory.db.wal` as it
+    /// happens, replay that log on `open`, and empty it on `save`, so changes made since the
+    /// last save survive a crash. Changes made through `entry` or `merge_from` aren't logged.
+    pub wal: bool,
+    /// When the handle writes its changes to disk without being asked to.
+    pub flush_policy: FlushPolicy,
+    /// Cap the map at this many entries: an `insert` that goes over evicts the least recently
+    /// used entry other than the one being inserted, as if by `remove`, where `get` and `insert`
+    /// count as uses. Entries loaded on `open` count as older than any used since. Inserts
+    /// through `entry` and `merge_from` don't evict, so the map can exceed the cap until the next
+    /// `insert`. Finding the entry to evict scans the whole map. Since the entry being inserted
+    /// is never evicted, `Some(0)` behaves like `Some(1)`.
+    pub max_entries: Option<usize>,
+    /// Record when each value was last written, for `Database::modified_at`, and save values
+    /// wrapped in `Modified` to keep those times. Files saved without this option don't open
+    /// with it, and vice versa. Changes made through `entry` or `merge_from` keep a key's
+    /// previous time, and keys they add are stamped by the next save; values replayed from the
+    /// write-ahead log are stamped when they're replayed.
+    pub track_modified: bool,
+    /// Checked by `insert` against every value, and again by `save`, which also catches values
+    /// changed through `entry`.
+    pub json_schema: Option<JsonSchema>,
+}
+
+/// When a `Database` saves on its own, set with `DatabaseOptions::flush_policy`. Saves triggered
+/// by the policy happen inside the mutating call; if one fails, the error is logged as a warning
+/// and the changes stay pending for the next trigger, so call `flush` where failures matter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Only on `save` or `flush`, or on drop with `DatabaseOptions::auto_save_on_drop`.
+    #[default]
+    Manual,
+    /// Once `n` changes have been made since the last write.
+    EveryN(usize),
+    /// On the first change made at least this long after the last write. Changes made within the
+    /// interval wait for a later change, an explicit `flush`, or the handle being dropped.
+    Interval(Duration),
+}
+
+/// A mutation of a `Database`, passed to the listeners registered with `Database::on_change`.
+#[derive(Debug)]
+pub enum ChangeEvent<'a, T> {
+    Inserted { key: &'a str, value: &'a T },
+    Removed { key: &'a str, old: &'a T },
+    Updated { key: &'a str, old: &'a T, new: &'a T },
+}
+
+/// A callback registered with `Database::on_change`. It has to be `Send + Sync` so that a
+/// `Database` with listeners can still be wrapped in a `SharedDatabase`.
+pub type ChangeListener<T> = Box<dyn Fn(&ChangeEvent<'_, T>) + Send + Sync>;
+
+/// A stateful handle over a database directory: the map is loaded once on `open` and kept in
+/// memory, and only written back when `save` is called.
+///
+/// The handle tracks whether the map changed since it was loaded or last saved, and `save` is a
+/// no-op when it hasn't, so calling it defensively doesn't rewrite the file.
+pub struct Database<T> where T: Serialize {
+    path: PathBuf,
+    data: DB<T>,
+    dirty: bool,
+    options: DatabaseOptions,
+    // Each listener with the id it was registered under, for `remove_listener`.
+    listeners: Vec<(u64, ChangeListener<T>)>,
+    next_listener: u64,
+    // With `case_insensitive`, maps each lowercased key to the casing it's stored under.
+    cased: HashMap<String, String>,
+    // The write-ahead log, opened for appending, when `DatabaseOptions::wal` is set.
+    wal: Option<fs::File>,
+    // Changes made since the last write, and when that was, for `DatabaseOptions::flush_policy`.
+    pending: usize,
+    last_flush: Instant,
+    // With `max_entries`, when each key was last used. Behind a mutex so `get` can record uses
+    // through `&self`.
+    recency: Mutex<Recency>,
+    // Set by `in_memory`: nothing is ever read from or written to disk.
+    in_memory: bool,
+    metrics: Metrics,
+    // With `track_modified`, when each key was last written.
+    modified: ModifiedTimes,
+    // The data file as this handle last loaded or saved it, so `SharedDatabase::watch` can tell
+    // its own saves from other writers'.
+    synced: Option<Fingerprint>,
+}
+
+// A data file's size and modification time.
+pub(crate) type Fingerprint = (u64, SystemTime);
+
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata: fs::Metadata = fs::metadata(get_db_path(path.to_str()?)).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+// A clock that ticks once per use, and the tick at which each key was last used. Keys that
+// haven't been used since the handle was opened have no tick.
+#[derive(Debug, Default)]
+struct Recency {
+    clock: u64,
+    last_used: HashMap<String, u64>,
+}
+
+impl<T> std::fmt::Debug for Database<T> where T: Serialize + std::fmt::Debug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Database")
+            .field("path", &self.path)
+            .field("data", &self.data)
+            .field("dirty", &self.dirty)
+            .field("options", &self.options)
+            .field("listeners", &self.listeners.len())
+            .field("wal", &self.wal.is_some())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl<T> Database<T> where T: Serialize + DeserializeOwned {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DBError> {
+        Self::open_with_options(path, DatabaseOptions::default())
+    }
+
+    pub fn open_with_options(path: impl AsRef<Path>, options: DatabaseOptions) -> Result<Self, DBError> {
+        let path = path.as_ref().to_path_buf();
+        // Taken first, so that a save racing the load shows up as a change.
+        let synced: Option<Fingerprint> = fingerprint(&path);
+        let (data, modified) = load_tracked(path_str(&path)?, options.track_modified)?;
+        let mut db = Database { path, data, dirty: false, options, listeners: Vec::new(), next_listener: 0, cased: HashMap::new(), wal: None, pending: 0, last_flush: Instant::now(), recency: Mutex::default(), in_memory: false, metrics: Metrics::default(), modified, synced };
+        db.metrics.record_load();
+        db.rebuild_cased();
+        if db.options.wal {
+            db.replay_wal()?;
+        }
+        Ok(db)
+    }
+
+    /// A handle that never touches the disk: it starts empty, `save` and `flush` do nothing, and
+    /// no file or directory is ever created for it. Its `path` is empty.
+    pub fn in_memory() -> Self {
+        Self::in_memory_with_options(DatabaseOptions::default())
+    }
+
+    /// Like `in_memory`, with `options`. Those about persistence, such as `wal` and
+    /// `flush_policy`, have no effect.
+    pub fn in_memory_with_options(options: DatabaseOptions) -> Self {
+        let mut db = Database {
+            path: PathBuf::new(),
+            data: HashMap::new(),
+            dirty: false,
+            options: DatabaseOptions { wal: false, ..options },
+            listeners: Vec::new(),
+            next_listener: 0,
+            cased: HashMap::new(),
+            wal: None,
+            pending: 0,
+            last_flush: Instant::now(),
+            recency: Mutex::default(),
+            in_memory: true,
+            metrics: Metrics::default(),
+            modified: ModifiedTimes::new(),
+            synced: None,
+        };
+        db.rebuild_cased();
+        db
+    }
+
+    // Applies the operations logged since the last save on top of the freshly loaded file, then
+    // opens the log for appending. A last line without its newline was cut short by a crash
+    // mid-append, so it's dropped rather than treated as corruption, and truncated away so the
+    // next logged operation doesn't get glued onto it.
+    fn replay_wal(&mut self) -> Result<(), DBError> {
+        let path: &str = path_str(&self.path)?;
+        ensure_db_dir(path)?;
+        let wal_path: String = get_wal_path(path);
+        let contents: String = match fs::read_to_string(&wal_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let complete: &str = contents.rfind('\n').map_or("", |end| &contents[..=end]);
+        for (index, line) in complete.lines().enumerate() {
+            if let Some(raw_key) = line.strip_prefix('-') {
+                let key: String = decode_key(raw_key)?;
+                if self.data.remove(&key).is_some() {
+                    self.cased.remove(&key.to_lowercase());
+                    self.modified.remove(&key);
+                }
+            } else if let Some((raw_key, value)) = line.strip_prefix('+').and_then(|op| op.split_once('=')) {
+                let key: String = decode_key(raw_key)?;
+                let value: T = serde_json::from_str(value)?;
+                self.take_differently_cased(&key);
+                self.stamp(&key);
+                self.data.insert(key, value);
+            } else {
+                return Err(DBError::MalformedLine { line: index + 1, content: line.to_string() });
+            }
+            self.dirty = true;
+        }
+        let wal: fs::File = fs::OpenOptions::new().create(true).append(true).open(&wal_path)?;
+        if complete.len() < contents.len() {
+            wal.set_len(complete.len() as u64)?;
+        }
+        self.wal = Some(wal);
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        let stored: &str = self.stored_key(key);
+        let value: Option<&T> = self.data.get(stored);
+        if value.is_some() {
+            self.touch(stored);
+        }
+        value
+    }
+
+    /// Registers `listener` to be called after every `insert`, `remove`, `extend`, `rename` and
+    /// `clear`. Changes made through `entry` or `merge_from` aren't reported.
+    ///
+    /// Listeners run synchronously, in registration order, while the handle is mutably
+    /// borrowed, so they can't re-enter the database; no lock is held on the file.
+    pub fn on_change(&mut self, listener: ChangeListener<T>) {
+        self.add_listener(listener);
+    }
+
+    // `on_change`, returning an id that `remove_listener` takes.
+    pub(crate) fn add_listener(&mut self, listener: ChangeListener<T>) -> u64 {
+        let id: u64 = self.next_listener;
+        self.next_listener += 1;
+        self.listeners.push((id, listener));
+        id
+    }
+
+    pub(crate) fn remove_listener(&mut self, id: u64) {
+        self.listeners.retain(|(listener_id, _)| *listener_id != id);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn listener_count(&self) -> usize {
+        self.listeners.len()
+    }
+
+    /// Inserts `value`, returning the value it replaced. Fails without changing anything if the
+    /// key doesn't pass `DatabaseOptions::key_validator`, or the value doesn't satisfy
+    /// `DatabaseOptions::json_schema`.
+    pub fn insert(&mut self, key: impl Into<String>, value: T) -> Result<Option<T>, DBError> {
+        let key: String = key.into();
+        self.options.key_validator.validate(&key)?;
+        if let Some(schema) = &self.options.json_schema {
+            schema.validate(&key, &value)?;
+        }
+        self.log_insert(&key, &value)?;
+        self.dirty = true;
+        let recased: Option<T> = self.take_differently_cased(&key);
+        let old: Option<T> = self.data.insert(key.clone(), value).or(recased);
+        self.metrics.record_insert();
+        self.stamp(&key);
+        if !self.listeners.is_empty() {
+            let new: &T = &self.data[&key];
+            match &old {
+                Some(old) => notify(&self.listeners, &ChangeEvent::Updated { key: &key, old, new }),
+                None => notify(&self.listeners, &ChangeEvent::Inserted { key: &key, value: new }),
+            }
+        }
+        self.touch(&key);
+        self.evict_beyond_cap(&key);
+        self.record_change();
+        Ok(old)
+    }
+
+    /// The value under `key`, first inserting `f()` if there isn't one. `f` is only called on a
+    /// miss, and only a miss marks the handle dirty. Fails like `insert` on an invalid key.
+    pub fn get_or_insert_with<F>(&mut self, key: impl Into<String>, f: F) -> Result<&T, DBError> where F: FnOnce() -> T {
+        let key: String = key.into();
+        if self.get(&key).is_none() {
+            self.insert(key.clone(), f())?;
+        }
+        Ok(&self.data[self.stored_key(&key)])
+    }
+
+    /// Removes `key`, returning its value. With `DatabaseOptions::wal`, a failure to log the
+    /// removal is only reported as a warning; the removal still happens in memory and is written
+    /// by the next `save`.
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let stored: String = self.stored_key(key).to_string();
+        if self.data.contains_key(&stored) {
+            self.log_removal_or_warn(&stored);
+        }
+        let removed: Option<T> = self.data.remove(&stored);
+        self.dirty |= removed.is_some();
+        if let Some(old) = &removed {
+            self.cased.remove(&key.to_lowercase());
+            self.modified.remove(&stored);
+            notify(&self.listeners, &ChangeEvent::Removed { key: &stored, old });
+        }
+        if removed.is_some() {
+            self.metrics.record_removes(1);
+            self.recency.get_mut().unwrap_or_else(PoisonError::into_inner).last_used.remove(&stored);
+            self.record_change();
+        }
+        removed
+    }
+
+    /// Inserts every pair from `iter`; later pairs win over earlier ones for the same key. Stops
+    /// at the first invalid key, keeping the pairs inserted before it.
+    pub fn extend<I>(&mut self, iter: I) -> Result<(), DBError> where I: IntoIterator<Item = (String, T)> {
+        for (key, value) in iter {
+            self.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Merges `other` into the in-memory map; see `merge`.
+    pub fn merge_from(&mut self, other: DB<T>, strategy: &MergeStrategy<T>) {
+        let changed: bool = if self.options.case_insensitive {
+            let other: DB<T> = other.into_iter().map(|(key, value)| (self.stored_key(&key).to_string(), value)).collect();
+            let changed: bool = merge_changed(&mut self.data, other, strategy);
+            self.rebuild_cased();
+            changed
+        } else {
+            merge_changed(&mut self.data, other, strategy)
+        };
+        if changed {
+            self.dirty = true;
+            self.record_change();
+        }
+    }
+
+    /// Moves the value under `old` to `new` in memory, with the same rules as `rename_key`.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<bool, DBError> {
+        self.options.key_validator.validate(new)?;
+        let old: String = self.stored_key(old).to_string();
+        let old: &str = &old;
+        if self.options.case_insensitive {
+            let recasing: bool = old.to_lowercase() == new.to_lowercase();
+            if !recasing && self.cased.contains_key(&new.to_lowercase()) {
+                return Err(DBError::KeyExists(self.stored_key(new).to_string()));
+            }
+        }
+        let renamed: bool = rename_entry(&mut self.data, old, new)?;
+        if renamed && old != new && self.wal.is_some() {
+            let line: String = wal_insert_line(new, &self.data[new])?;
+            self.log_removal(old)?;
+            self.append_to_wal(&line)?;
+        }
+        if renamed && old != new && let Some(time) = self.modified.remove(old) {
+            self.modified.insert(new.to_string(), time);
+        }
+        if renamed && self.options.case_insensitive {
+            self.cased.remove(&old.to_lowercase());
+            self.cased.insert(new.to_lowercase(), new.to_string());
+        }
+        if renamed && old != new {
+            self.dirty = true;
+            let value: &T = &self.data[new];
+            notify(&self.listeners, &ChangeEvent::Removed { key: old, old: value });
+            notify(&self.listeners, &ChangeEvent::Inserted { key: new, value });
+            self.record_change();
+        }
+        Ok(renamed)
+    }
+
+    /// The in-memory entry for `key`, for in-place read-or-create, like `HashMap::entry`.
+    pub fn entry(&mut self, key: impl Into<String>) -> Entry<'_, T> {
+        let key: String = key.into();
+        if !self.options.case_insensitive {
+            return Entry { inner: self.data.entry(key), dirty: &mut self.dirty, cased: None };
+        }
+        let key: String = self.cased.get(&key.to_lowercase()).cloned().unwrap_or(key);
+        Entry { inner: self.data.entry(key), dirty: &mut self.dirty, cased: Some(&mut self.cased) }
+    }
+
+    /// Empties the in-memory map. Once saved, the old contents stay recoverable from the backup
+    /// the save takes. WAL failures are handled as in `remove`.
+    pub fn clear(&mut self) {
+        self.dirty |= !self.data.is_empty();
+        self.cased.clear();
+        self.modified.clear();
+        self.recency.get_mut().unwrap_or_else(PoisonError::into_inner).last_used.clear();
+        let keys: Vec<String> = if self.wal.is_some() { self.data.keys().cloned().collect() } else { Vec::new() };
+        for key in &keys {
+            self.log_removal_or_warn(key);
+        }
+        let cleared: bool = !self.data.is_empty();
+        self.metrics.record_removes(self.data.len() as u64);
+        for (key, old) in self.data.drain() {
+            notify(&self.listeners, &ChangeEvent::Removed { key: &key, old: &old });
+        }
+        if cleared {
+            self.record_change();
+        }
+    }
+
+    /// The entries whose value satisfies `predicate`. This is a full O(n) scan of the map.
+    pub fn filter<F>(&self, predicate: F) -> HashMap<&str, &T> where F: Fn(&T) -> bool {
+        self.data.iter().filter(|(_, value)| predicate(value)).map(|(key, value)| (key.as_str(), value)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Iterates over the entries in arbitrary order. Unlike `get`, this doesn't count as using
+    /// them for `DatabaseOptions::max_entries`.
+    pub fn iter(&self) -> hash_map::Iter<'_, String, T> {
+        self.data.iter()
+    }
+
+    pub fn keys(&self) -> hash_map::Keys<'_, String, T> {
+        self.data.keys()
+    }
+
+    pub fn values(&self) -> hash_map::Values<'_, String, T> {
+        self.data.values()
+    }
+
+    /// Writes the map back to disk if it changed since it was loaded or last saved, then empties
+    /// the write-ahead log, whose operations the file now includes.
+    pub fn save(&mut self) -> Result<(), DBError> {
+        if !self.dirty || self.in_memory {
+            return Ok(());
+        }
+        let report: SaveReport = self.write()?;
+        self.metrics.record_save(&report);
+        self.dirty = false;
+        self.pending = 0;
+        self.last_flush = Instant::now();
+        self.truncate_wal()
+    }
+
+    /// Writes pending changes now, whatever the `DatabaseOptions::flush_policy`. The same as
+    /// `save`, under the name that reads better when saves are otherwise automatic.
+    pub fn flush(&mut self) -> Result<(), DBError> {
+        self.save()
+    }
+
+    // Removes least recently used entries until the map fits `max_entries`, leaving `keep`, the
+    // key just inserted, alone.
+    fn evict_beyond_cap(&mut self, keep: &str) {
+        let Some(cap) = self.options.max_entries else { return };
+        // `keep` itself is never a victim, so a cap of 0 stops at 1.
+        while self.data.len() > cap.max(1) {
+            let victim: Option<String> = {
+                let recency = self.recency.get_mut().unwrap_or_else(PoisonError::into_inner);
+                self.data.keys()
+                    .filter(|key| key.as_str() != keep)
+                    .min_by_key(|key| recency.last_used.get(key.as_str()).copied().unwrap_or(0))
+                    .cloned()
+            };
+            let Some(victim) = victim else { return };
+            self.remove(&victim);
+        }
+    }
+
+    // Counts a change towards `DatabaseOptions::flush_policy`, and saves if that's now due.
+    // Changes made through `entry` aren't counted, but are written by whichever save comes next.
+    fn record_change(&mut self) {
+        self.pending += 1;
+        let due: bool = match self.options.flush_policy {
+            FlushPolicy::Manual => false,
+            FlushPolicy::EveryN(n) => self.pending >= n,
+            FlushPolicy::Interval(interval) => self.last_flush.elapsed() >= interval,
+        };
+        if due && let Err(e) = self.save() {
+            log::warn!("automatic flush of {} failed: {}", self.path.display(), e);
+        }
+    }
+
+    // Swaps in a map freshly loaded from disk, read when the file looked like `synced`. Only
+    // called on a clean handle, so there are no in-memory changes to lose, and the write-ahead
+    // log is left alone.
+    pub(crate) fn replace_data(&mut self, data: DB<T>, modified: ModifiedTimes, synced: Option<Fingerprint>) {
+        self.data = data;
+        self.modified = modified;
+        self.synced = synced;
+        self.metrics.record_load();
+        self.rebuild_cased();
+        self.recency.get_mut().unwrap_or_else(PoisonError::into_inner).last_used.clear();
+    }
+
+    // The data file's current fingerprint, if it differs from the one this handle last loaded or
+    // saved, i.e. if someone else has written it since.
+    pub(crate) fn changed_on_disk(&self) -> Option<Option<Fingerprint>> {
+        let current: Option<Fingerprint> = fingerprint(&self.path);
+        (current != self.synced).then_some(current)
+    }
+
+    pub(crate) fn data(&self) -> &DB<T> {
+        &self.data
+    }
+
+    pub(crate) fn options(&self) -> &DatabaseOptions {
+        &self.options
+    }
+
+    // Applies a transaction's changes, `Some` to insert and `None` to remove, and saves. If the
+    // save fails, the map is put back as it was and nothing is reported to the listeners.
+    pub(crate) fn commit_changes(&mut self, changes: HashMap<String, Option<T>>) -> Result<(), DBError> {
+        let was_dirty: bool = self.dirty;
+        let mut undo: Vec<(String, Option<T>)> = Vec::with_capacity(changes.len());
+        for (key, change) in changes {
+            let old: Option<T> = match change {
+                Some(value) => self.data.insert(key.clone(), value),
+                None => self.data.remove(&key),
+            };
+            undo.push((key, old));
+        }
+        if undo.is_empty() {
+            return Ok(());
+        }
+        let mut undo_times: Vec<(String, Option<DateTime<FixedOffset>>)> = Vec::new();
+        if self.options.track_modified {
+            let now: DateTime<FixedOffset> = modified::now();
+            for (key, _) in &undo {
+                let time = if self.data.contains_key(key) { self.modified.insert(key.clone(), now) } else { self.modified.remove(key) };
+                undo_times.push((key.clone(), time));
+            }
+        }
+        self.dirty = true;
+        if let Err(e) = self.save() {
+            for (key, old) in undo {
+                match old {
+                    Some(old) => self.data.insert(key, old),
+                    None => self.data.remove(&key),
+                };
+            }
+            for (key, time) in undo_times {
+                match time {
+                    Some(time) => self.modified.insert(key, time),
+                    None => self.modified.remove(&key),
+                };
+            }
+            self.dirty = was_dirty;
+            return Err(e);
+        }
+        self.rebuild_cased();
+        for (key, old) in &undo {
+            match (old, self.data.get(key)) {
+                (Some(old), Some(new)) => notify(&self.listeners, &ChangeEvent::Updated { key, old, new }),
+                (None, Some(value)) => notify(&self.listeners, &ChangeEvent::Inserted { key, value }),
+                (Some(old), None) => notify(&self.listeners, &ChangeEvent::Removed { key, old }),
+                (None, None) => {}
+            }
+            if self.data.contains_key(key) {
+                self.metrics.record_insert();
+                self.touch(key);
+            } else if old.is_some() {
+                self.metrics.record_removes(1);
+                self.recency.get_mut().unwrap_or_else(PoisonError::into_inner).last_used.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// A snapshot of the handle's counters; see `MetricsSnapshot`.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// When the value under `key` was last written, with `DatabaseOptions::track_modified`.
+    /// `None` if there's no such key, tracking is off, or the key was added through `entry` or
+    /// `merge_from` and hasn't been saved since.
+    pub fn modified_at(&self, key: &str) -> Option<DateTime<FixedOffset>> {
+        self.modified.get(self.stored_key(key)).copied()
+    }
+
+    /// Whether there are changes that `save` would write.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether this handle was created by `in_memory`.
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+}
+
+/// A view into a single key of a `Database`, returned by `Database::entry`. Any access that hands
+/// out a mutable reference marks the handle dirty, since the value may have been changed through it.
+#[derive(Debug)]
+pub struct Entry<'a, T> {
+    inner: hash_map::Entry<'a, String, T>,
+    dirty: &'a mut bool,
+    // With `case_insensitive`, the database's casings, which learn the key once it's inserted.
+    cased: Option<&'a mut HashMap<String, String>>,
+}
+
+impl<'a, T> Entry<'a, T> {
+    pub fn key(&self) -> &str {
+        self.inner.key()
+    }
+
+    pub fn or_insert(mut self, default: T) -> &'a mut T {
+        *self.dirty = true;
+        self.record_casing();
+        self.inner.or_insert(default)
+    }
+
+    pub fn or_insert_with<F>(mut self, default: F) -> &'a mut T where F: FnOnce() -> T {
+        *self.dirty = true;
+        self.record_casing();
+        self.inner.or_insert_with(default)
+    }
+
+    pub fn and_modify<F>(self, f: F) -> Self where F: FnOnce(&mut T) {
+        let Entry { inner, dirty, cased } = self;
+        if let hash_map::Entry::Occupied(_) = inner {
+            *dirty = true;
+        }
+        Entry { inner: inner.and_modify(f), dirty, cased }
+    }
+
+    // Called right before a vacant entry is filled, so a key is only known by its casing once
+    // it's actually stored.
+    fn record_casing(&mut self) {
+        if let (Some(cased), hash_map::Entry::Vacant(vacant)) = (self.cased.as_deref_mut(), &self.inner) {
+            cased.insert(vacant.key().to_lowercase(), vacant.key().clone());
+        }
+    }
+}
+
+impl<T> Database<T> where T: Serialize {
+    fn save_options(&self) -> SaveOptions {
+        SaveOptions { key_validator: self.options.key_validator, json_schema: self.options.json_schema.clone(), ..SaveOptions::default() }
+    }
+
+    // Saves the map as it is. With `track_modified`, keys without a time yet are stamped with
+    // the time of the save.
+    fn write(&mut self) -> Result<SaveReport, DBError> {
+        let path: &str = path_str(&self.path)?;
+        let report: SaveReport = if self.options.track_modified {
+            let now: DateTime<FixedOffset> = modified::now();
+            for key in self.data.keys() {
+                if !self.modified.contains_key(key) {
+                    self.modified.insert(key.clone(), now);
+                }
+            }
+            save_with_reporting(path, &stamp(&self.data, &self.modified), &self.save_options(), &JsonCodec)?
+        } else {
+            save_with_reporting(path, &self.data, &self.save_options(), &JsonCodec)?
+        };
+        self.synced = fingerprint(&self.path);
+        Ok(report)
+    }
+
+    // What `drop` does: saves unsaved changes if the options ask for that, ignoring errors.
+    fn save_before_drop(&mut self) {
+        let flushes: bool = self.options.auto_save_on_drop || self.options.flush_policy != FlushPolicy::Manual;
+        if self.dirty && flushes && !self.in_memory && self.write().is_ok() {
+            let _ = self.truncate_wal();
+        }
+    }
+
+    fn stamp(&mut self, key: &str) {
+        if self.options.track_modified {
+            self.modified.insert(key.to_string(), modified::now());
+        }
+    }
+
+    // WAL lines use the data file's key encoding: `+<key>=<json>` for an insert and `-<key>` for
+    // a removal. Each is synced to disk as soon as it's written.
+    fn log_insert(&mut self, key: &str, value: &T) -> Result<(), DBError> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let line: String = wal_insert_line(key, value)?;
+        self.append_to_wal(&line)
+    }
+
+    fn log_removal(&mut self, key: &str) -> Result<(), DBError> {
+        let line: String = format!("-{}\n", encode_key(key));
+        self.append_to_wal(&line)
+    }
+
+    fn log_removal_or_warn(&mut self, key: &str) {
+        if let Err(e) = self.log_removal(key) {
+            log::warn!("failed to log removal of {} to the write-ahead log: {}", key, e);
+        }
+    }
+
+    fn append_to_wal(&mut self, line: &str) -> Result<(), DBError> {
+        if let Some(wal) = &mut self.wal {
+            wal.write_all(line.as_bytes())?;
+            wal.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn truncate_wal(&mut self) -> Result<(), DBError> {
+        if let Some(wal) = &mut self.wal {
+            wal.set_len(0)?;
+        }
+        Ok(())
+    }
+
+    // The key `key` is stored under: itself, unless `case_insensitive` maps it to another casing.
+    pub(crate) fn stored_key<'a>(&'a self, key: &'a str) -> &'a str {
+        if !self.options.case_insensitive {
+            return key;
+        }
+        self.cased.get(&key.to_lowercase()).map_or(key, String::as_str)
+    }
+
+    // Records `key` as the casing of its lowercased form, and removes the value stored under a
+    // previous casing, if there was one, so the insert replaces it.
+    fn take_differently_cased(&mut self, key: &str) -> Option<T> {
+        if !self.options.case_insensitive {
+            return None;
+        }
+        match self.cased.insert(key.to_lowercase(), key.to_string()) {
+            Some(previous) if previous != key => {
+                self.modified.remove(&previous);
+                self.data.remove(&previous)
+            }
+            _ => None,
+        }
+    }
+
+    fn touch(&self, key: &str) {
+        if self.options.max_entries.is_none() {
+            return;
+        }
+        let mut recency = self.recency.lock().unwrap_or_else(PoisonError::into_inner);
+        recency.clock += 1;
+        let tick: u64 = recency.clock;
+        recency.last_used.insert(key.to_string(), tick);
+    }
+
+    fn rebuild_cased(&mut self) {
+        self.cased.clear();
+        if self.options.case_insensitive {
+            self.cased.extend(self.data.keys().map(|key| (key.to_lowercase(), key.clone())));
+        }
+    }
+}
+
+impl<T> Drop for Database<T> where T: Serialize {
+    fn drop(&mut self) {
+        self.save_before_drop();
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Database<T> where T: Serialize {
+    type Item = (&'a String, &'a T);
+    type IntoIter = hash_map::Iter<'a, String, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+impl<T> IntoIterator for Database<T> where T: Serialize {
+    type Item = (String, T);
+    type IntoIter = hash_map::IntoIter<String, T>;
+
+    /// Consumes the handle, yielding its entries in arbitrary order. Unsaved changes are saved
+    /// first if dropping the handle would save them, and are otherwise lost, as on drop.
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.save_before_drop();
+        // Nothing is left to save once the map has been moved out.
+        self.dirty = false;
+        std::mem::take(&mut self.data).into_iter()
+    }
+}
+
+fn wal_insert_line<T>(key: &str, value: &T) -> Result<String, DBError> where T: Serialize {
+    Ok(format!("+{}={}\n", encode_key(key), serde_json::to_string(value)?))
+}
+
+fn notify<T>(listeners: &[(u64, ChangeListener<T>)], event: &ChangeEvent<'_, T>) {
+    for (_, listener) in listeners {
+        listener(event);
+    }
+}
+
+// The free functions address databases by `&str`, so paths must be valid UTF-8.
+fn path_str(path: &Path) -> Result<&str, DBError> {
+    path.to_str().ok_or_else(|| DBError::InvalidPath(path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{get_wal_path, list_backups, load_db};
+
+    #[test]
+    fn open_insert_save_reopen_round_trip() {
+        let path = "target/test_database_round_trip";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).expect("opening a new db should succeed");
+        assert_eq!(db.get("a"), None);
+        assert_eq!(db.insert("a", 1).unwrap(), None);
+        assert_eq!(db.insert("b", 2).unwrap(), None);
+        assert_eq!(db.insert("a", 3).unwrap(), Some(1));
+        db.save().expect("saving should succeed");
+
+        let mut reopened: Database<u32> = Database::open(path).expect("reopening should succeed");
+        assert_eq!(reopened.get("a"), Some(&3));
+        assert_eq!(reopened.get("b"), Some(&2));
+
+        assert_eq!(reopened.remove("b"), Some(2));
+        reopened.save().expect("saving should succeed");
+
+        let reopened: Database<u32> = Database::open(path).expect("reopening should succeed");
+        assert_eq!(reopened.get("b"), None);
+        assert_eq!(reopened.get("a"), Some(&3));
+    }
+
+    #[test]
+    fn unsaved_changes_are_not_persisted() {
+        let path = "target/test_database_unsaved";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<String> = Database::open(path).unwrap();
+        db.insert("draft", "not saved".to_string()).unwrap();
+
+        let reopened: Database<String> = Database::open(path).unwrap();
+        assert_eq!(reopened.get("draft"), None);
+    }
+
+    #[test]
+    fn save_without_changes_is_a_no_op() {
+        let path = "target/test_database_dirty";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        assert!(!db.is_dirty());
+        db.save().unwrap();
+        assert!(!fs::exists(path).unwrap());
+
+        db.insert("a", 1).unwrap();
+        assert!(db.is_dirty());
+        db.save().unwrap();
+        assert!(!db.is_dirty());
+        db.insert("b", 2).unwrap();
+        db.save().unwrap();
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+
+        // Removing a missing key changes nothing, so there's still nothing to save.
+        db.remove("missing");
+        assert!(!db.is_dirty());
+        db.save().unwrap();
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+
+        // Deleting the file proves the second save didn't touch the disk.
+        fs::remove_file(crate::get_db_path(path)).unwrap();
+        db.save().unwrap();
+        assert!(!fs::exists(crate::get_db_path(path)).unwrap());
+
+        db.clear();
+        assert!(db.is_dirty());
+    }
+
+    #[test]
+    fn entry_counts_in_place() {
+        let path = "target/test_database_entry";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        for _ in 0..5 {
+            db.entry("hits").and_modify(|v| *v += 1).or_insert(1);
+        }
+        assert_eq!(db.get("hits"), Some(&5));
+        *db.entry("misses").or_insert_with(|| 10) += 1;
+        assert_eq!(db.get("misses"), Some(&11));
+        assert_eq!(db.entry("hits").key(), "hits");
+        db.save().unwrap();
+        assert!(!db.is_dirty());
+
+        // Modifying a missing key inserts nothing and leaves the handle clean.
+        let _ = db.entry("absent").and_modify(|v| *v += 1);
+        assert!(!db.is_dirty());
+        db.entry("hits").and_modify(|v| *v += 1);
+        assert!(db.is_dirty());
+        db.save().unwrap();
+
+        let reopened: Database<u32> = Database::open(path).unwrap();
+        assert_eq!(reopened.get("hits"), Some(&6));
+    }
+
+    #[test]
+    fn clear_then_save_empties_the_file() {
+        let path = "target/test_database_clear";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        db.insert("a", 1).unwrap();
+        db.save().unwrap();
+        db.clear();
+        assert!(db.is_empty());
+        db.save().unwrap();
+
+        assert!(Database::<u32>::open(path).unwrap().is_empty());
+        assert_eq!(crate::restore_latest_backup::<u32>(path).unwrap()["a"], 1);
+    }
+
+    #[test]
+    fn rename_in_memory() {
+        let mut db: Database<u32> = Database::open("target/test_database_rename_never_saved").unwrap();
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        assert!(db.rename("a", "c").unwrap());
+        assert_eq!(db.get("c"), Some(&1));
+        assert!(matches!(db.rename("c", "b"), Err(DBError::KeyExists(_))));
+        assert!(!db.rename("a", "d").unwrap());
+        assert_eq!(db.len(), 2);
+    }
+
+    #[test]
+    fn extend_inserts_in_bulk() {
+        let path = "target/test_database_extend";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        db.extend(Vec::new()).unwrap();
+        assert!(!db.is_dirty());
+        db.extend((0..3000).map(|i| (format!("key{}", i), i)).chain([("key0".to_string(), 42)])).unwrap();
+        assert!(db.is_dirty());
+        db.save().unwrap();
+
+        let reopened: Database<u32> = Database::open(path).unwrap();
+        assert_eq!(reopened.len(), 3000);
+        assert_eq!(reopened.get("key0"), Some(&42));
+        assert_eq!(reopened.get("key2999"), Some(&2999));
+    }
+
+    #[test]
+    fn filter_by_value() {
+        let mut db: Database<u32> = Database::open("target/test_database_filter_never_saved").unwrap();
+        db.extend([("ann".to_string(), 25), ("bob".to_string(), 31), ("cat".to_string(), 47)]).unwrap();
+
+        let older: HashMap<&str, &u32> = db.filter(|age| *age > 30);
+        assert_eq!(older.len(), 2);
+        assert_eq!(older["bob"], &31);
+        assert!(!older.contains_key("ann"));
+    }
+
+    #[test]
+    fn merge_from_marks_dirty_only_on_change() {
+        let path = "target/test_database_merge";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        db.merge_from(HashMap::from([("a".to_string(), 1)]), &MergeStrategy::PreferExisting);
+        assert!(db.is_dirty());
+        db.save().unwrap();
+
+        db.merge_from(HashMap::from([("a".to_string(), 2)]), &MergeStrategy::PreferExisting);
+        assert!(!db.is_dirty());
+        db.merge_from(HashMap::from([("a".to_string(), 2)]), &MergeStrategy::PreferIncoming);
+        assert!(db.is_dirty());
+        assert_eq!(db.get("a"), Some(&2));
+    }
+
+    #[test]
+    fn change_listeners_see_every_mutation() {
+        use std::sync::{Arc, Mutex};
+
+        let mut db: Database<u32> = Database::open("target/test_database_listeners_never_saved").unwrap();
+        let seen: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        for name in ["first", "second"] {
+            let seen = Arc::clone(&seen);
+            db.on_change(Box::new(move |event: &ChangeEvent<'_, u32>| {
+                let line = match event {
+                    ChangeEvent::Inserted { key, value } => format!("{} inserted {}={}", name, key, value),
+                    ChangeEvent::Removed { key, old } => format!("{} removed {}={}", name, key, old),
+                    ChangeEvent::Updated { key, old, new } => format!("{} updated {}={}->{}", name, key, old, new),
+                };
+                seen.lock().unwrap().push(line);
+            }));
+        }
+
+        db.insert("a", 1).unwrap();
+        db.insert("a", 2).unwrap();
+        db.remove("a");
+        db.remove("a");
+        assert_eq!(*seen.lock().unwrap(), [
+            "first inserted a=1", "second inserted a=1",
+            "first updated a=1->2", "second updated a=1->2",
+            "first removed a=2", "second removed a=2",
+        ]);
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected_on_insert() {
+        let mut db: Database<u32> = Database::open("target/test_database_invalid_keys_never_saved").unwrap();
+        assert!(matches!(db.insert("", 1), Err(DBError::InvalidKey { .. })));
+        assert!(matches!(db.insert("two\nlines", 1), Err(DBError::InvalidKey { .. })));
+        assert!(db.is_empty());
+        assert!(!db.is_dirty());
+
+        let options = DatabaseOptions { key_validator: KeyValidator::permissive(), ..DatabaseOptions::default() };
+        let path = "target/test_database_permissive_keys";
+        let _ = fs::remove_dir_all(path);
+        let mut db: Database<u32> = Database::open_with_options(path, options).unwrap();
+        db.insert("two\nlines", 1).unwrap();
+        db.save().unwrap();
+        assert_eq!(Database::<u32>::open(path).unwrap().get("two\nlines"), Some(&1));
+    }
+
+    #[test]
+    fn case_insensitive_keys_resolve_to_one_entry() {
+        let path = "target/test_database_case_insensitive";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { case_insensitive: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        db.insert("Alice", 1).unwrap();
+        assert_eq!(db.get("ALICE"), Some(&1));
+        assert_eq!(db.get("alice"), Some(&1));
+        assert_eq!(db.insert("ALICE", 2).unwrap(), Some(1));
+        assert_eq!(db.len(), 1);
+        db.entry("alice").and_modify(|v| *v += 1);
+        assert_eq!(db.get("Alice"), Some(&3));
+        db.insert("\u{C9}mile", 4).unwrap();
+        assert_eq!(db.get("\u{E9}MILE"), Some(&4));
+        db.save().unwrap();
+
+        // The latest casing is the one on disk.
+        let reopened: Database<u32> = Database::open_with_options(path, options).unwrap();
+        assert_eq!(crate::get_one::<u32>(path, "ALICE").unwrap(), Some(3));
+        assert_eq!(reopened.get("aLiCe"), Some(&3));
+
+        let mut reopened = reopened;
+        assert!(reopened.rename("alice", "Alicia").unwrap());
+        assert!(matches!(reopened.rename("ALICIA", "\u{E9}mile"), Err(DBError::KeyExists(_))));
+        assert_eq!(reopened.remove("ALICIA"), Some(3));
+        assert_eq!(reopened.get("alicia"), None);
+        assert_eq!(reopened.len(), 1);
+    }
+
+    #[test]
+    fn vacant_entries_leave_no_casing_behind() {
+        let options = DatabaseOptions { case_insensitive: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::in_memory_with_options(options);
+        db.insert("alice", 1).unwrap();
+        db.entry("Bob").and_modify(|v| *v += 1);
+        assert!(db.rename("alice", "bob").unwrap());
+        assert_eq!(db.get("BOB"), Some(&1));
+
+        *db.entry("Carol").or_insert(2) += 1;
+        assert_eq!(db.get("carol"), Some(&3));
+        assert!(matches!(db.rename("bob", "CAROL"), Err(DBError::KeyExists(_))));
+        *db.entry("CAROL").or_insert_with(|| 0) += 1;
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.get("Carol"), Some(&4));
+    }
+
+    #[test]
+    fn get_or_insert_with_only_computes_on_a_miss() {
+        let path = "target/test_database_get_or_insert_with";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        let mut calls: u32 = 0;
+        assert_eq!(*db.get_or_insert_with("a", || { calls += 1; 7 }).unwrap(), 7);
+        assert_eq!(*db.get_or_insert_with("a", || { calls += 1; 8 }).unwrap(), 7);
+        assert_eq!(calls, 1);
+        db.save().unwrap();
+
+        assert_eq!(*db.get_or_insert_with("a", || 9).unwrap(), 7);
+        assert!(!db.is_dirty());
+        assert_eq!(Database::<u32>::open(path).unwrap().get("a"), Some(&7));
+    }
+
+    #[test]
+    fn auto_save_on_drop_flushes_dirty_handles() {
+        let path = "target/test_database_auto_save";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { auto_save_on_drop: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options).unwrap();
+        db.insert("a", 1).unwrap();
+        drop(db);
+
+        let reopened: Database<u32> = Database::open(path).unwrap();
+        assert_eq!(reopened.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn handles_iterate_like_maps() {
+        let path = "target/test_database_iter";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { auto_save_on_drop: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options).unwrap();
+        db.extend([("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3)]).unwrap();
+
+        let mut keys: Vec<&String> = db.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "b", "c"]);
+        assert_eq!(db.values().sum::<u32>(), 6);
+        let mut total: u32 = 0;
+        for (key, value) in &db {
+            assert_eq!(db.get(key), Some(value));
+            total += value;
+        }
+        assert_eq!(total, db.iter().map(|(_, value)| value).sum::<u32>());
+
+        // Consuming the handle saves it, as dropping it would.
+        let mut entries: Vec<(String, u32)> = db.into_iter().collect();
+        entries.sort();
+        assert_eq!(entries, [("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3)]);
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn every_n_policy_batches_writes() {
+        let path = "target/test_database_flush_every_n";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { flush_policy: FlushPolicy::EveryN(5), ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options).unwrap();
+        for i in 0..4 {
+            db.insert(format!("key{}", i), i).unwrap();
+        }
+        assert!(!Path::new(&format!("{}/memory.db", path)).exists());
+
+        db.insert("key4", 4).unwrap();
+        assert!(!db.is_dirty());
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 5);
+        // Every write of new contents but the first takes a backup, so no backup means one write.
+        assert!(list_backups(path).unwrap().is_empty());
+
+        db.insert("key5", 5).unwrap();
+        drop(db);
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 6);
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn max_entries_evicts_the_least_recently_used() {
+        let path = "target/test_database_lru";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { max_entries: Some(3), ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        db.insert("c", 3).unwrap();
+        db.save().unwrap();
+        assert_eq!(db.get("a"), Some(&1));
+
+        db.insert("d", 4).unwrap();
+        assert!(db.is_dirty());
+        assert_eq!(db.len(), 3);
+        assert_eq!(db.get("b"), None);
+        db.insert("c", 30).unwrap();
+        db.insert("e", 5).unwrap();
+        assert_eq!(db.get("a"), None);
+        db.save().unwrap();
+        drop(db);
+
+        let reopened: Database<u32> = Database::open_with_options(path, options).unwrap();
+        let mut keys: Vec<&String> = reopened.data().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["c", "d", "e"]);
+
+        let mut capped: Database<u32> = Database::in_memory_with_options(DatabaseOptions { max_entries: Some(0), ..DatabaseOptions::default() });
+        capped.insert("a", 1).unwrap();
+        capped.insert("b", 2).unwrap();
+        assert_eq!(capped.len(), 1);
+        assert_eq!(capped.get("b"), Some(&2));
+    }
+
+    #[test]
+    fn in_memory_handles_never_touch_the_disk() {
+        let before: Vec<PathBuf> = fs::read_dir(".").unwrap().map(|entry| entry.unwrap().path()).collect();
+
+        let options = DatabaseOptions { auto_save_on_drop: true, wal: true, flush_policy: FlushPolicy::EveryN(1), ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::in_memory_with_options(options);
+        assert!(db.is_in_memory());
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        assert_eq!(db.get("a"), Some(&1));
+        assert_eq!(db.remove("a"), Some(1));
+        assert_eq!(db.len(), 1);
+        db.save().unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let after: Vec<PathBuf> = fs::read_dir(".").unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(before, after);
+        assert!(!Path::new("memory.db").exists());
+    }
+
+    #[test]
+    fn wal_recovers_unsaved_changes_after_a_crash() {
+        let path = "target/test_database_wal";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { wal: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        db.insert("saved", 1).unwrap();
+        db.save().unwrap();
+        assert_eq!(fs::read_to_string(get_wal_path(path)).unwrap(), "");
+
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        db.insert("a", 3).unwrap();
+        db.remove("saved");
+        db.rename("b", "c").unwrap();
+        // A crash: the handle goes away without saving.
+        drop(db);
+
+        let plain: Database<u32> = Database::open(path).unwrap();
+        assert_eq!(plain.get("saved"), Some(&1));
+        assert_eq!(plain.get("a"), None);
+
+        // An append torn by the crash is ignored.
+        fs::OpenOptions::new().append(true).open(get_wal_path(path)).unwrap().write_all(b"+d=4").unwrap();
+
+        let mut recovered: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        assert!(recovered.is_dirty());
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(recovered.get("a"), Some(&3));
+        assert_eq!(recovered.get("c"), Some(&2));
+        recovered.save().unwrap();
+        assert_eq!(fs::read_to_string(get_wal_path(path)).unwrap(), "");
+        drop(recovered);
+
+        let reopened: Database<u32> = Database::open_with_options(path, options).unwrap();
+        assert!(!reopened.is_dirty());
+        assert_eq!(reopened.len(), 2);
+    }
+
+    #[test]
+    fn wal_appends_after_a_torn_tail_replay_cleanly() {
+        let path = "target/test_database_wal_torn_tail";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { wal: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        db.insert("ab", 1).unwrap();
+        db.insert("cd", 2).unwrap();
+        drop(db);
+        fs::OpenOptions::new().append(true).open(get_wal_path(path)).unwrap().write_all(b"-ab").unwrap();
+
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        assert_eq!(db.len(), 2);
+        db.remove("cd");
+        db.insert("e", 5).unwrap();
+        drop(db);
+        fs::OpenOptions::new().append(true).open(get_wal_path(path)).unwrap().write_all(b"+d=").unwrap();
+
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        assert_eq!(db.get("ab"), Some(&1));
+        assert_eq!(db.get("cd"), None);
+        db.insert("f", 6).unwrap();
+        drop(db);
+
+        let reopened: Database<u32> = Database::open_with_options(path, options).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.get("e"), Some(&5));
+        assert_eq!(reopened.get("f"), Some(&6));
+    }
+}
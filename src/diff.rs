@@ -0,0 +1,92 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::de::DeserializeOwned;
+
+use crate::{load_db, DBError, DB};
+
+/// What changed between two snapshots of a database, as returned by `diff`. Keys are kept
+/// sorted so the diff prints in a stable order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbDiff<T> {
+    /// Keys only in the new snapshot.
+    pub added: BTreeSet<String>,
+    /// Keys only in the old snapshot.
+    pub removed: BTreeSet<String>,
+    /// Keys in both whose value differs, with the `(old, new)` values.
+    pub changed: BTreeMap<String, (T, T)>,
+}
+
+impl<T> DbDiff<T> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares two snapshots key by key.
+pub fn diff<T>(old: &DB<T>, new: &DB<T>) -> DbDiff<T> where T: PartialEq + Clone {
+    let mut result = DbDiff { added: BTreeSet::new(), removed: BTreeSet::new(), changed: BTreeMap::new() };
+    for (key, old_value) in old {
+        match new.get(key) {
+            None => {
+                result.removed.insert(key.clone());
+            }
+            Some(new_value) if new_value != old_value => {
+                result.changed.insert(key.clone(), (old_value.clone(), new_value.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    result.added.extend(new.keys().filter(|key| !old.contains_key(*key)).cloned());
+    result
+}
+
+/// Loads the databases under `old_path` and `new_path` and diffs them, e.g. to compare a backup
+/// restored into its own directory with the live database.
+pub fn diff_files<T>(old_path: &str, new_path: &str) -> Result<DbDiff<T>, DBError> where T: DeserializeOwned + PartialEq + Clone {
+    let old: DB<T> = load_db(old_path)?;
+    let new: DB<T> = load_db(new_path)?;
+    Ok(diff(&old, &new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::save_db;
+
+    #[test]
+    fn diff_classifies_every_key() {
+        let old: DB<u32> = HashMap::from([
+            ("same".to_string(), 1),
+            ("changed".to_string(), 2),
+            ("removed".to_string(), 3),
+        ]);
+        let new: DB<u32> = HashMap::from([
+            ("same".to_string(), 1),
+            ("changed".to_string(), 20),
+            ("added".to_string(), 4),
+        ]);
+
+        let result = diff(&old, &new);
+        assert_eq!(result.added, BTreeSet::from(["added".to_string()]));
+        assert_eq!(result.removed, BTreeSet::from(["removed".to_string()]));
+        assert_eq!(result.changed, BTreeMap::from([("changed".to_string(), (2, 20))]));
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn diff_files_loads_both_sides() {
+        let old_path = "target/test_diff_old";
+        let new_path = "target/test_diff_new";
+
+        let _ = fs::remove_dir_all(old_path);
+        let _ = fs::remove_dir_all(new_path);
+
+        save_db(old_path, &HashMap::from([("a".to_string(), 1)])).unwrap();
+        save_db(new_path, &HashMap::from([("b".to_string(), 1)])).unwrap();
+
+        let result: DbDiff<u32> = diff_files(old_path, new_path).unwrap();
+        assert_eq!(result.added, BTreeSet::from(["b".to_string()]));
+        assert_eq!(result.removed, BTreeSet::from(["a".to_string()]));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use serde::ser::Serialize;
+use serde_json::Value;
+
+use crate::schema::read_schema_version;
+use crate::{contents_differ, get_db_path, load_db, read_file_bytes, validate_keys, write_records, DBError, JsonCodec, SaveOptions, DB};
+
+/// What `save_db` would do, as worked out by `save_db_dry_run`. Keys are sorted so the report
+/// prints in a stable order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// Keys the save would add to the file.
+    pub added: BTreeSet<String>,
+    /// Keys in the file that the save would drop.
+    pub removed: BTreeSet<String>,
+    /// Keys in both whose value would change.
+    pub changed: BTreeSet<String>,
+    /// The data file's current size, 0 if there isn't one.
+    pub current_bytes: u64,
+    /// The size the data file would have after the save.
+    pub new_bytes: u64,
+    /// Whether the save would back up the current file first.
+    pub would_back_up: bool,
+}
+
+impl DryRunReport {
+    /// How much the data file would grow, negative if it would shrink.
+    pub fn byte_delta(&self) -> i64 {
+        self.new_bytes as i64 - self.current_bytes as i64
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Serializes `contents` exactly as `save_db` would and compares the result with what's on disk,
+/// without writing, locking or creating anything. Values are compared as JSON, so formatting
+/// alone doesn't count as a change. Fails wherever `save_db` would fail before writing.
+pub fn save_db_dry_run<T>(path: &str, contents: &DB<T>) -> Result<DryRunReport, DBError> where T: Serialize {
+    let options = SaveOptions { schema_version: read_schema_version(path)?, ..SaveOptions::default() };
+    validate_keys(contents, &options)?;
+    let mut output: Vec<u8> = Vec::new();
+    write_records(&mut output, contents, &options, &JsonCodec)?;
+
+    let file_path: String = get_db_path(path);
+    let current: Option<Vec<u8>> = match read_file_bytes(Path::new(&file_path)) {
+        Ok(bytes) => Some(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let would_back_up: bool = options.backup_policy.takes_backups() && current.as_ref().is_some_and(|current| contents_differ(current, &output));
+    let current_bytes: u64 = if current.is_some() { fs::metadata(&file_path)?.len() } else { 0 };
+
+    let old: DB<Value> = load_db(path)?;
+    let mut report = DryRunReport {
+        added: BTreeSet::new(),
+        removed: old.keys().filter(|key| !contents.contains_key(*key)).cloned().collect(),
+        changed: BTreeSet::new(),
+        current_bytes,
+        new_bytes: output.len() as u64,
+        would_back_up,
+    };
+    for (key, value) in contents {
+        match old.get(key) {
+            None => {
+                report.added.insert(key.clone());
+            }
+            Some(old_value) if *old_value != serde_json::to_value(value)? => {
+                report.changed.insert(key.clone());
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{save_db, save_db_reporting, SaveReport};
+
+    #[test]
+    fn dry_run_predicts_the_real_save() {
+        let path = "target/test_dry_run";
+
+        let _ = fs::remove_dir_all(path);
+
+        let first: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let report: DryRunReport = save_db_dry_run(path, &first).unwrap();
+        assert!(!fs::exists(path).unwrap());
+        assert_eq!(report.added, BTreeSet::from(["a".to_string(), "b".to_string()]));
+        assert_eq!((report.current_bytes, report.new_bytes, report.would_back_up), (0, 8, false));
+        save_db(path, &first).unwrap();
+
+        let second: DB<u32> = HashMap::from([("a".to_string(), 10), ("c".to_string(), 3)]);
+        let report: DryRunReport = save_db_dry_run(path, &second).unwrap();
+        assert_eq!(fs::read_to_string(get_db_path(path)).unwrap().len(), 8);
+        assert_eq!(report.added, BTreeSet::from(["c".to_string()]));
+        assert_eq!(report.removed, BTreeSet::from(["b".to_string()]));
+        assert_eq!(report.changed, BTreeSet::from(["a".to_string()]));
+        assert_eq!(report.byte_delta(), 1);
+
+        let saved: SaveReport = save_db_reporting(path, &second).unwrap();
+        assert_eq!(saved.bytes_written as u64, report.new_bytes);
+        assert_eq!(saved.backup_path.is_some(), report.would_back_up);
+
+        let report: DryRunReport = save_db_dry_run(path, &second).unwrap();
+        assert!(report.is_unchanged());
+        assert!(!report.would_back_up);
+        assert_eq!(report.byte_delta(), 0);
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{commit, ensure_db_dir, get_db_path, parse_db, validate_keys, write_records, DBError, FileLock, JsonCodec, SaveOptions, DB, ENCRYPTED_MAGIC};
+
+// AES-GCM's standard 96-bit nonce, stored right after the magic bytes.
+const NONCE_LEN: usize = 12;
+
+/// Saves `contents` encrypted with AES-256-GCM under `key`. The file holds the usual `key=value`
+/// records, encrypted as a whole behind a fresh random nonce, so nothing about the data is
+/// readable without the key, and any change to the file is detected on load.
+///
+/// Backups are copies of earlier encrypted files, so they're encrypted too. Since every save
+/// uses a new nonce, saving unchanged contents still takes a backup.
+pub fn save_db_encrypted<T>(path: &str, contents: &DB<T>, key: &[u8; 32]) -> Result<(), DBError> where T: Serialize {
+    let options = SaveOptions::default();
+    validate_keys(contents, &options)?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, options.lock_timeout)?;
+    commit(path, &options, |writer| {
+        let mut plaintext: Vec<u8> = Vec::new();
+        let count: usize = write_records(&mut plaintext, contents, &options, &JsonCodec)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext: Vec<u8> = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| std::io::Error::other("encryption failed"))?;
+        writer.write_all(ENCRYPTED_MAGIC)?;
+        writer.write_all(&nonce)?;
+        writer.write_all(&ciphertext)?;
+        Ok(count)
+    })
+}
+
+/// Loads a database written by `save_db_encrypted`. A missing file loads as an empty database.
+/// A wrong key, or a file that was altered in any way, fails with `DBError::Decryption` rather
+/// than returning garbage; an unencrypted database fails with `DBError::WrongFormat`.
+pub fn load_db_encrypted<T>(path: &str, key: &[u8; 32]) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let file_path = get_db_path(path);
+    let bytes: Vec<u8> = match std::fs::read(Path::new(&file_path)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(DB::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(payload) = bytes.strip_prefix(ENCRYPTED_MAGIC) else {
+        return Err(DBError::WrongFormat(format!("{} is not an encrypted database", file_path)));
+    };
+    if payload.len() < NONCE_LEN {
+        return Err(DBError::Decryption(file_path));
+    }
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let plaintext: Vec<u8> = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| DBError::Decryption(file_path.clone()))?;
+    let contents = String::from_utf8(plaintext).map_err(|_| DBError::Decryption(file_path))?;
+    parse_db(&contents, false, &JsonCodec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::{list_backups, load_db, BACKUP_NAME_FORMAT};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn encrypted_round_trip() {
+        let path = "target/test_encrypted_round_trip";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(load_db_encrypted::<String>(path, &KEY).unwrap().is_empty());
+
+        let db: DB<String> = HashMap::from([("card".to_string(), "4111 1111 1111 1111".to_string())]);
+        save_db_encrypted(path, &db, &KEY).unwrap();
+        save_db_encrypted(path, &db, &KEY).unwrap();
+
+        let raw: Vec<u8> = fs::read(get_db_path(path)).unwrap();
+        assert!(!raw.windows(4).any(|window| window == b"4111"));
+        assert_eq!(load_db_encrypted::<String>(path, &KEY).unwrap(), db);
+        assert!(matches!(load_db::<String>(path), Err(DBError::WrongFormat(_))));
+
+        // The second save backed up the first, still encrypted.
+        let backups = list_backups(path).unwrap();
+        let latest = backups.last().unwrap().format(BACKUP_NAME_FORMAT).to_string();
+        let backup: Vec<u8> = fs::read(Path::new(path).join("backups").join(latest)).unwrap();
+        assert!(backup.starts_with(ENCRYPTED_MAGIC));
+        assert!(!backup.windows(4).any(|window| window == b"4111"));
+    }
+
+    #[test]
+    fn wrong_keys_and_tampering_are_detected() {
+        let path = "target/test_encrypted_wrong_key";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1)]);
+        save_db_encrypted(path, &db, &KEY).unwrap();
+        assert!(matches!(load_db_encrypted::<u32>(path, &[8; 32]), Err(DBError::Decryption(_))));
+
+        let mut bytes: Vec<u8> = fs::read(get_db_path(path)).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(get_db_path(path), &bytes).unwrap();
+        assert!(matches!(load_db_encrypted::<u32>(path, &KEY), Err(DBError::Decryption(_))));
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum DBError {
+    Io(std::io::Error),
+    Serde(serde_json::Error),
+    Csv(csv::Error),
+    Bincode(bincode::Error),
+    Watch(notify::Error),
+    Yaml(serde_yaml::Error),
+    /// A value `TomlCodec` couldn't encode or decode.
+    Toml(String),
+    /// A non-blank line that isn't a `key=value` record. `line` is 1-based.
+    MalformedLine { line: usize, content: String },
+    /// A key that appears on more than one line, rejected by `load_db_strict`.
+    DuplicateKey { key: String, lines: Vec<usize> },
+    /// A file in the backups directory whose name isn't a backup timestamp.
+    BackupParse(String),
+    /// The named backup, or any backup at all, doesn't exist.
+    NotFound(String),
+    /// A key on disk whose percent-encoding doesn't decode to UTF-8.
+    InvalidKeyEncoding(String),
+    /// A value that can't be stored in the line format.
+    InvalidValue { key: String, reason: String },
+    /// Another saver held the lock file for longer than the configured timeout.
+    LockTimeout(PathBuf),
+    /// A thread panicked while holding a `SharedDatabase` lock.
+    LockPoisoned,
+    /// A database path that isn't valid UTF-8.
+    InvalidPath(PathBuf),
+    /// A record whose value doesn't match the checksum saved alongside it.
+    ChecksumMismatch { key: String },
+    /// An encrypted file that didn't decrypt: the key is wrong, or the file was altered.
+    Decryption(String),
+    /// A snapshot label that isn't safe to use as a file name.
+    InvalidLabel { label: String, reason: String },
+    /// An `update_if_version` whose expected version no longer matches the one on disk.
+    VersionConflict { key: String, expected: u64, actual: u64 },
+    /// A key rejected by a `KeyValidator`.
+    InvalidKey { key: String, reason: String },
+    /// A value that doesn't satisfy the configured `JsonSchema`, with one message per violation.
+    SchemaViolation { key: String, errors: Vec<String> },
+    /// Renaming onto a key that's already in use.
+    KeyExists(String),
+    /// A `DbConfig` whose options can't be combined.
+    InvalidConfig(String),
+    /// The file is in a different on-disk format than the loader expects, e.g. binary vs text.
+    WrongFormat(String),
+    /// The operation can't be performed on this database, e.g. appending to a compressed file.
+    Unsupported(String),
+}
+
+impl std::fmt::Display for DBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DBError::Io(e) => write!(f, "IO error: {}", e),
+            DBError::Serde(e) => write!(f, "Serde error: {}", e),
+            DBError::Csv(e) => write!(f, "CSV error: {}", e),
+            DBError::Bincode(e) => write!(f, "Bincode error: {}", e),
+            DBError::Watch(e) => write!(f, "Watch error: {}", e),
+            DBError::Yaml(e) => write!(f, "YAML error: {}", e),
+            DBError::Toml(e) => write!(f, "TOML error: {}", e),
+            DBError::MalformedLine { line, content } => write!(f, "Malformed line {}: {}", line, content),
+            DBError::DuplicateKey { key, lines } => write!(f, "Duplicate key {} on lines {:?}", key, lines),
+            DBError::BackupParse(name) => write!(f, "Invalid backup file name: {}", name),
+            DBError::NotFound(what) => write!(f, "Not found: {}", what),
+            DBError::InvalidKeyEncoding(key) => write!(f, "Invalid key encoding: {}", key),
+            DBError::InvalidValue { key, reason } => write!(f, "Invalid value for key {}: {}", key, reason),
+            DBError::LockTimeout(path) => write!(f, "Timed out waiting for lock on {}", path.display()),
+            DBError::LockPoisoned => write!(f, "Database lock poisoned"),
+            DBError::InvalidPath(path) => write!(f, "Database path is not valid UTF-8: {}", path.display()),
+            DBError::ChecksumMismatch { key } => write!(f, "Checksum mismatch for key {}", key),
+            DBError::Decryption(file) => write!(f, "Could not decrypt {}: wrong key or corrupted file", file),
+            DBError::InvalidLabel { label, reason } => write!(f, "Invalid snapshot label {:?}: {}", label, reason),
+            DBError::VersionConflict { key, expected, actual } => {
+                write!(f, "Version conflict for key {}: expected version {}, found {}", key, expected, actual)
+            }
+            DBError::InvalidKey { key, reason } => write!(f, "Invalid key {}: {}", key, reason),
+            DBError::SchemaViolation { key, errors } => write!(f, "Value for key {} violates the schema: {}", key, errors.join("; ")),
+            DBError::KeyExists(key) => write!(f, "Key already exists: {}", key),
+            DBError::InvalidConfig(what) => write!(f, "Invalid configuration: {}", what),
+            DBError::WrongFormat(what) => write!(f, "Wrong format: {}", what),
+            DBError::Unsupported(what) => write!(f, "Unsupported operation: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for DBError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DBError::Io(e) => Some(e),
+            DBError::Serde(e) => Some(e),
+            DBError::Csv(e) => Some(e),
+            DBError::Bincode(e) => Some(e),
+            DBError::Watch(e) => Some(e),
+            DBError::Yaml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for DBError {
+    fn from(e: serde_json::Error) -> Self {
+        DBError::Serde(e)
+    }
+}
+
+impl From<csv::Error> for DBError {
+    fn from(e: csv::Error) -> Self {
+        DBError::Csv(e)
+    }
+}
+
+impl From<bincode::Error> for DBError {
+    fn from(e: bincode::Error) -> Self {
+        DBError::Bincode(e)
+    }
+}
+
+impl From<notify::Error> for DBError {
+    fn from(e: notify::Error) -> Self {
+        DBError::Watch(e)
+    }
+}
+
+impl From<serde_yaml::Error> for DBError {
+    fn from(e: serde_yaml::Error) -> Self {
+        DBError::Yaml(e)
+    }
+}
+
+impl From<std::io::Error> for DBError {
+    fn from(e: std::io::Error) -> Self {
+        DBError::Io(e)
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use csv::StringRecord;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{DBError, DB};
+
+/// Writes the whole database to `out_path` as a single pretty-printed JSON object, with keys in
+/// sorted order. An empty database is written as `{}`.
+pub fn export_json<T>(db: &DB<T>, out_path: &str) -> Result<(), DBError> where T: Serialize {
+    let sorted: BTreeMap<&String, &T> = db.iter().collect();
+    let mut json = serde_json::to_string_pretty(&sorted)?;
+    json.push('\n');
+    fs::write(out_path, json)?;
+    Ok(())
+}
+
+/// Parses the file at `path`, a single top-level JSON object written by hand or by another tool,
+/// into a database with one entry per field. A leading byte order mark is skipped.
+pub fn load_db_from_json_object<T>(path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(contents.strip_prefix('\u{feff}').unwrap_or(&contents))?)
+}
+
+/// Reads a file written by `export_json` back into a database; the same as
+/// `load_db_from_json_object`.
+pub fn import_json<T>(in_path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    load_db_from_json_object(in_path)
+}
+
+/// Writes the database as CSV with the key in the first column, headed `key`, and the value's
+/// fields in the following columns, in key order. `T` must serialize as a flat struct: nested
+/// structs, sequences and bare scalars can't be mapped onto columns and are rejected.
+pub fn export_csv<T>(db: &DB<T>, out_path: &str) -> Result<(), DBError> where T: Serialize {
+    let mut entries: Vec<(&String, &T)> = db.iter().collect();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+
+    let mut writer = csv::Writer::from_path(out_path)?;
+    let mut wrote_header = false;
+    for (key, value) in entries {
+        let (header, fields) = flatten_value(key, value)?;
+        if !wrote_header {
+            writer.write_record(std::iter::once("key").chain(header.iter()))?;
+            wrote_header = true;
+        }
+        writer.write_record(std::iter::once(key.as_str()).chain(fields.iter()))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a CSV file in the layout written by `export_csv`: the first column is the key and the
+/// remaining columns are deserialized into `T` by header name.
+pub fn import_csv<T>(in_path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let mut reader = csv::Reader::from_path(in_path)?;
+    let value_headers: StringRecord = reader.headers()?.iter().skip(1).collect();
+    let mut db: DB<T> = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let key = record.get(0).unwrap_or_default().to_string();
+        let fields: StringRecord = record.iter().skip(1).collect();
+        db.insert(key, fields.deserialize(Some(&value_headers))?);
+    }
+    Ok(db)
+}
+
+// Runs the value through csv's own struct serializer, which derives headers from field names
+// and refuses anything that isn't a flat struct, then reads back the header and the row.
+fn flatten_value<T>(key: &str, value: &T) -> Result<(StringRecord, StringRecord), DBError> where T: Serialize {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(value).map_err(|e| DBError::InvalidValue {
+        key: key.to_string(),
+        reason: format!("CSV export needs a flat struct: {}", e),
+    })?;
+    let bytes = writer.into_inner().map_err(|e| DBError::Io(e.into_error()))?;
+    let mut reader = csv::Reader::from_reader(bytes.as_slice());
+    let header = reader.headers()?.clone();
+    let fields = reader.records().next().transpose()?.unwrap_or_default();
+    Ok((header, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let out_path = "target/test_export.json";
+
+        let mut db: DB<Vec<u32>> = HashMap::new();
+        db.insert("b".to_string(), vec![1, 2]);
+        db.insert("a=weird\nkey".to_string(), vec![]);
+        export_json(&db, out_path).expect("exporting should succeed");
+
+        let contents = fs::read_to_string(out_path).unwrap();
+        assert!(contents.find("\"a=weird\\nkey\"").unwrap() < contents.find("\"b\"").unwrap());
+
+        let imported: DB<Vec<u32>> = import_json(out_path).expect("importing should succeed");
+        assert_eq!(imported, db);
+    }
+
+    #[test]
+    fn empty_database_exports_as_empty_object() {
+        let out_path = "target/test_export_empty.json";
+
+        let db: DB<u32> = HashMap::new();
+        export_json(&db, out_path).expect("exporting should succeed");
+
+        assert_eq!(fs::read_to_string(out_path).unwrap(), "{}\n");
+        assert!(import_json::<u32>(out_path).unwrap().is_empty());
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, Deserialize)]
+    struct Person {
+        age: u32,
+        city: String,
+    }
+
+    #[test]
+    fn hand_written_json_objects_load() {
+        let in_path = "target/test_import_hand_written.json";
+
+        fs::write(in_path, "{ \"a\": 1, \"b\": 2 }").unwrap();
+        let imported: DB<i32> = load_db_from_json_object(in_path).unwrap();
+        assert_eq!(imported, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]));
+
+        fs::write(in_path, "\u{feff}{\n  \"ann\": { \"age\": 30, \"city\": \"Oslo\" }\n}\n").unwrap();
+        let imported: DB<Person> = load_db_from_json_object(in_path).unwrap();
+        assert_eq!(imported["ann"], Person { age: 30, city: "Oslo".to_string() });
+
+        fs::write(in_path, "[1, 2]").unwrap();
+        assert!(matches!(load_db_from_json_object::<i32>(in_path), Err(DBError::Serde(_))));
+    }
+
+    #[test]
+    fn csv_round_trip_keyed_by_name() {
+        let out_path = "target/test_export.csv";
+
+        let mut db: DB<Person> = HashMap::new();
+        db.insert("alice".to_string(), Person { age: 31, city: "Paris, France".to_string() });
+        db.insert("bob".to_string(), Person { age: 45, city: "Oslo".to_string() });
+        export_csv(&db, out_path).expect("exporting should succeed");
+
+        let contents = fs::read_to_string(out_path).unwrap();
+        assert_eq!(contents, "key,age,city\nalice,31,\"Paris, France\"\nbob,45,Oslo\n");
+
+        let imported: DB<Person> = import_csv(out_path).expect("importing should succeed");
+        assert_eq!(imported, db);
+    }
+
+    #[test]
+    fn csv_export_rejects_nested_values() {
+        #[derive(serde::Serialize)]
+        struct Team {
+            lead: Person,
+        }
+
+        let mut db: DB<Team> = HashMap::new();
+        db.insert("team".to_string(), Team { lead: Person { age: 1, city: "x".to_string() } });
+
+        let err = export_csv(&db, "target/test_export_nested.csv").expect_err("nested values should be rejected");
+        assert!(matches!(err, DBError::InvalidValue { ref key, .. } if key == "team"));
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+use serde::ser::Serialize;
+use serde_json::Value;
+
+use crate::{DBError, DB};
+
+/// A compiled JSON Schema that values must satisfy before they're written, set with
+/// `SaveOptions::json_schema`, `DbConfig::json_schema` or `DatabaseOptions::json_schema`.
+/// Values are checked in their JSON form, whatever their Rust type or the codec in use.
+/// Cloning shares the compiled schema.
+#[derive(Debug, Clone)]
+pub struct JsonSchema {
+    validator: Arc<jsonschema::Validator>,
+}
+
+impl JsonSchema {
+    /// Compiles `schema`, failing with `DBError::InvalidConfig` if it isn't a valid JSON Schema.
+    pub fn new(schema: &Value) -> Result<Self, DBError> {
+        let validator = jsonschema::validator_for(schema).map_err(|e| DBError::InvalidConfig(format!("invalid JSON Schema: {}", e)))?;
+        Ok(JsonSchema { validator: Arc::new(validator) })
+    }
+
+    /// Fails with `DBError::SchemaViolation` if `value` doesn't satisfy the schema.
+    pub fn validate<T>(&self, key: &str, value: &T) -> Result<(), DBError> where T: Serialize {
+        let value: Value = serde_json::to_value(value)?;
+        let errors: Vec<String> = self.validator.iter_errors(&value).map(|e| e.to_string()).collect();
+        if !errors.is_empty() {
+            return Err(DBError::SchemaViolation { key: key.to_string(), errors });
+        }
+        Ok(())
+    }
+}
+
+// Checks every value against `schema`, if there is one. Keys are checked in sorted order, so
+// the violation reported first doesn't depend on `HashMap` iteration order.
+pub(crate) fn validate_values<T>(contents: &DB<T>, schema: Option<&JsonSchema>) -> Result<(), DBError> where T: Serialize {
+    let Some(schema) = schema else { return Ok(()) };
+    let mut keys: Vec<&String> = contents.keys().collect();
+    keys.sort();
+    for key in keys {
+        schema.validate(key, &contents[key])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use serde_json::json;
+    use crate::{load_db, save_db_with_options, Database, DatabaseOptions, SaveOptions};
+
+    fn schema() -> JsonSchema {
+        JsonSchema::new(&json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "port": { "type": "integer", "minimum": 1 } },
+            "required": ["name", "port"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn inserts_must_satisfy_the_schema() {
+        let options = DatabaseOptions { json_schema: Some(schema()), ..DatabaseOptions::default() };
+        let mut db: Database<Value> = Database::in_memory_with_options(options);
+        db.insert("web", json!({ "name": "web", "port": 80 })).unwrap();
+
+        let err = db.insert("db", json!({ "name": "db", "port": 0 })).unwrap_err();
+        assert!(matches!(&err, DBError::SchemaViolation { key, errors } if key == "db" && errors.len() == 1));
+        assert!(db.get("db").is_none());
+        assert_eq!(db.len(), 1);
+
+        // Without a schema, anything goes.
+        let mut db: Database<Value> = Database::in_memory();
+        db.insert("db", json!("not even an object")).unwrap();
+    }
+
+    #[test]
+    fn saves_must_satisfy_the_schema() {
+        let path = "target/test_json_schema_save";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = SaveOptions { json_schema: Some(schema()), ..SaveOptions::default() };
+        let good: DB<Value> = HashMap::from([("web".to_string(), json!({ "name": "web", "port": 80 }))]);
+        save_db_with_options(path, &good, &options).unwrap();
+
+        let mut bad: DB<Value> = good.clone();
+        bad.insert("db".to_string(), json!({ "name": "db" }));
+        let err = save_db_with_options(path, &bad, &options).unwrap_err();
+        assert!(matches!(err, DBError::SchemaViolation { key, .. } if key == "db"));
+        assert_eq!(load_db::<Value>(path).unwrap(), good);
+
+        assert!(matches!(JsonSchema::new(&json!({ "type": 7 })), Err(DBError::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{load_db, save_db, DBError, DB};
+
+/// A database keyed by any serde-serializable type rather than `String`.
+pub type KeyedDB<K, T> = HashMap<K, T>;
+
+/// Saves a map with non-string keys. Each key is stored as its JSON encoding on the left of the
+/// `=`, e.g. `42={...}` or `"Red"={...}`, and is escaped like any other key.
+pub fn save_keyed_db<K, T>(path: &str, contents: &KeyedDB<K, T>) -> Result<(), DBError> where K: Serialize, T: Serialize {
+    let mut encoded: DB<&T> = HashMap::with_capacity(contents.len());
+    for (key, value) in contents {
+        encoded.insert(serde_json::to_string(key)?, value);
+    }
+    save_db(path, &encoded)
+}
+
+/// Loads a map saved with `save_keyed_db`, deserializing each key back into `K`.
+pub fn load_keyed_db<K, T>(path: &str) -> Result<KeyedDB<K, T>, DBError> where K: DeserializeOwned + Eq + Hash, T: DeserializeOwned {
+    let raw: DB<T> = load_db(path)?;
+    let mut db: KeyedDB<K, T> = HashMap::with_capacity(raw.len());
+    for (key, value) in raw {
+        db.insert(serde_json::from_str(&key)?, value);
+    }
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use serde::Deserialize;
+
+    #[test]
+    fn integer_keys_round_trip() {
+        let path = "target/test_keyed_integers";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: KeyedDB<u64, String> = HashMap::new();
+        db.insert(1, "one".to_string());
+        db.insert(u64::MAX, "max".to_string());
+        save_keyed_db(path, &db).expect("saving should succeed");
+
+        let loaded: KeyedDB<u64, String> = load_keyed_db(path).expect("loading should succeed");
+        assert_eq!(loaded, db);
+    }
+
+    #[test]
+    fn enum_keys_round_trip() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, Deserialize)]
+        enum Color {
+            Red,
+            Custom { name: String },
+        }
+
+        let path = "target/test_keyed_enums";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: KeyedDB<Color, u32> = HashMap::new();
+        db.insert(Color::Red, 1);
+        db.insert(Color::Custom { name: "a=b".to_string() }, 2);
+        save_keyed_db(path, &db).expect("saving should succeed");
+
+        let loaded: KeyedDB<Color, u32> = load_keyed_db(path).expect("loading should succeed");
+        assert_eq!(loaded, db);
+    }
+}
@@ -0,0 +1,114 @@
+use std::any::Any;
+use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+
+use crate::{load_db, DBError, DB};
+
+/// A database whose values are kept as the JSON text read from the file, and only deserialized
+/// when `get` asks for them. Useful when a large file is loaded for a handful of its values.
+#[derive(Debug)]
+pub struct LazyDb {
+    raw: DB<Box<RawValue>>,
+    parsed: HashMap<String, Box<dyn Any>>,
+}
+
+/// Loads the database at `path` without deserializing any values. Every line is still checked
+/// the way `load_db` checks it, and each value must be well-formed JSON.
+pub fn load_lazy(path: &str) -> Result<LazyDb, DBError> {
+    Ok(LazyDb { raw: load_db(path)?, parsed: HashMap::new() })
+}
+
+impl LazyDb {
+    /// Deserializes the value at `key` as `T` the first time it's asked for, and returns the
+    /// cached result after that. Asking for the same key as a different type parses it again,
+    /// replacing the cached value.
+    pub fn get<T>(&mut self, key: &str) -> Result<Option<&T>, DBError> where T: DeserializeOwned + 'static {
+        let Some(raw) = self.raw.get(key) else {
+            return Ok(None);
+        };
+        if !self.parsed.get(key).is_some_and(|value| value.is::<T>()) {
+            let value: T = serde_json::from_str(raw.get())?;
+            self.parsed.insert(key.to_string(), Box::new(value));
+        }
+        Ok(self.parsed[key].downcast_ref::<T>())
+    }
+
+    /// The value at `key` as written in the file.
+    pub fn get_raw(&self, key: &str) -> Option<&RawValue> {
+        self.raw.get(key).map(|raw| raw.as_ref())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.raw.contains_key(key)
+    }
+
+    /// The keys in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.raw.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use serde::{Deserialize, Deserializer};
+    use crate::save_db;
+
+    // Panics when deserialized from anything but `7`.
+    #[derive(Debug, PartialEq)]
+    struct OnlySeven;
+
+    impl<'de> Deserialize<'de> for OnlySeven {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            let value = u32::deserialize(deserializer)?;
+            assert_eq!(value, 7, "a value other than key7's was parsed");
+            Ok(OnlySeven)
+        }
+    }
+
+    static PARSES: AtomicUsize = AtomicUsize::new(0);
+
+    // A `u32` that counts how often it's deserialized.
+    #[derive(Debug, PartialEq)]
+    struct Counted(u32);
+
+    impl<'de> Deserialize<'de> for Counted {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+            PARSES.fetch_add(1, Ordering::SeqCst);
+            u32::deserialize(deserializer).map(Counted)
+        }
+    }
+
+    #[test]
+    fn only_requested_values_are_parsed() {
+        let path = "target/test_lazy";
+
+        let _ = fs::remove_dir_all(path);
+        let db: DB<u32> = (0..50).map(|i| (format!("key{}", i), i)).collect();
+        save_db(path, &db).unwrap();
+
+        let mut lazy: LazyDb = load_lazy(path).unwrap();
+        assert_eq!(lazy.len(), 50);
+        assert_eq!(lazy.get::<OnlySeven>("key7").unwrap(), Some(&OnlySeven));
+        assert_eq!(lazy.get::<Counted>("key7").unwrap(), Some(&Counted(7)));
+        assert_eq!(lazy.get::<Counted>("key7").unwrap(), Some(&Counted(7)));
+        assert_eq!(PARSES.load(Ordering::SeqCst), 1);
+        assert_eq!(lazy.get_raw("key8").unwrap().get(), "8");
+        assert!(lazy.get::<Counted>("missing").unwrap().is_none());
+
+        // A different type for the same key parses it again.
+        assert_eq!(lazy.get::<u64>("key7").unwrap(), Some(&7));
+        assert!(matches!(lazy.get::<String>("key7"), Err(DBError::Serde(_))));
+    }
+}
@@ -1,155 +1,2953 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::Write as _;
 use std::fs;
-use std::io::Write;
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use chrono::DateTime;
 use chrono::FixedOffset;
-use serde_json;
+use chrono::TimeDelta;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
+use serde_json::value::RawValue;
 
-const MAX_BACKUPS: usize = 10;
+mod append;
+mod archive;
+mod autosave;
+mod backend;
+mod binary;
+mod borrowed;
+mod codec;
+mod collections;
+mod config;
+mod database;
+mod diff;
+mod dry_run;
+mod encrypted;
+mod error;
+mod export;
+mod json_schema;
+mod keyed;
+mod lazy;
+mod merge;
+mod metrics;
+mod modified;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod read_only;
+mod schema;
+mod separators;
+mod shared;
+mod snapshot;
+mod stats;
+mod stream;
+mod transaction;
+mod ttl;
+mod versioned;
+mod watch;
 
-#[derive(Debug)]
-pub struct DBError(String);
+pub use append::{append_entry, compact};
+pub use archive::{archive_backups, restore_from_archive};
+pub use autosave::AutosaveHandle;
+pub use backend::{load_db_from, save_db_to, Backend, FileBackend, MemoryBackend};
+pub use binary::{load_db_binary, save_db_binary};
+pub use borrowed::load_db_borrowed;
+pub use codec::{Codec, JsonCodec, TomlCodec, YamlCodec};
+pub use collections::{list_collections, load_collection, save_collection};
+pub use config::{load_db_with, save_db_with, DbConfig};
+pub use database::{ChangeEvent, ChangeListener, Database, DatabaseOptions, Entry, FlushPolicy};
+pub use diff::{diff, diff_files, DbDiff};
+pub use dry_run::{save_db_dry_run, DryRunReport};
+pub use encrypted::{load_db_encrypted, save_db_encrypted};
+pub use error::DBError;
+pub use export::{export_csv, export_json, import_csv, import_json, load_db_from_json_object};
+pub use json_schema::JsonSchema;
+pub use keyed::{load_keyed_db, save_keyed_db, KeyedDB};
+pub use lazy::{load_lazy, LazyDb};
+pub use merge::{merge, MergeStrategy, Resolver};
+pub use metrics::MetricsSnapshot;
+pub use modified::Modified;
+#[cfg(feature = "parallel")]
+pub use parallel::save_db_parallel;
+pub use read_only::ReadOnlyDatabase;
+pub use schema::{load_db_migrated, Migration};
+pub use shared::SharedDatabase;
+pub use snapshot::{restore_snapshot, snapshot};
+pub use stats::{stats, Stats};
+pub use stream::load_db_stream;
+pub use transaction::Txn;
+pub use ttl::{insert_with_ttl, load_db_expiring, save_db_expiring, Expiring, SweeperHandle};
+pub use versioned::{get_versioned, update_if_version, Versioned};
+pub use watch::WatchHandle;
+
+type BackupListing = (Vec<(DateTime<FixedOffset>, String)>, Vec<OsString>);
+
+const MAX_BACKUPS: usize = 10;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// Starts every file written by `save_db_binary`; can't be mistaken for a text record.
+const BINARY_MAGIC: &[u8] = b"\0memory_db binary v1\n";
+// Starts every file written by `save_db_encrypted`, followed by the nonce and ciphertext.
+const ENCRYPTED_MAGIC: &[u8] = b"\0memory_db aes-256-gcm v1\n";
+// Some editors start UTF-8 files with a byte order mark, which loading skips. Windows line
+// endings need no such care: `lines` drops the `\r` of each `\r\n`.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(5);
+// First line of a file saved with `SaveOptions::checksums`. It's a comment, so older loaders
+// still read the records; they just don't verify the `#crc` suffixes.
+const CHECKSUM_HEADER: &str = "#memory_db:crc32";
+// Written after the checksum header, if any, by saves with `SaveOptions::pretty`.
+const PRETTY_HEADER: &str = "#memory_db:pretty";
+// Starts the header line written with `SaveOptions::schema_version`, after the checksum header
+// if there is one. Also a comment to every other loader.
+const SCHEMA_HEADER_PREFIX: &str = "#schema=";
+// Backup file names avoid `:` (illegal on Windows) and sort in creation order.
+const BACKUP_NAME_FORMAT: &str = "%Y%m%dT%H%M%S%.9f%z";
 
 pub type DB<T> = HashMap<String, T>;
 
-impl std::fmt::Display for DBError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+/// Rules every key must pass before it's saved or inserted into a `Database`. The default
+/// rejects empty keys and keys containing a raw line break; `KeyValidator::permissive()` accepts
+/// anything the file format can escape.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyValidator {
+    /// Longest allowed key, in bytes.
+    pub max_len: Option<usize>,
+    pub allow_empty: bool,
+    /// Called for every character of the key; a `false` rejects the key.
+    pub allowed_char: Option<fn(char) -> bool>,
+    /// Reject keys containing the record or key-value separator, `\n` and `=` unless
+    /// `SaveOptions` says otherwise, instead of percent-escaping them in the file. Escaping, the
+    /// default, round-trips such keys, but leaves the file harder to read and to process with
+    /// line-oriented tools.
+    pub reject_separators: bool,
+}
+
+impl KeyValidator {
+    pub fn permissive() -> Self {
+        KeyValidator { max_len: None, allow_empty: true, allowed_char: None, reject_separators: false }
+    }
+
+    /// Checks `key` for a file with the default separators.
+    pub fn validate(&self, key: &str) -> Result<(), DBError> {
+        self.validate_separated(key, "\n", "=")
+    }
+
+    // `validate` for a file whose records end with `record_separator` and split on
+    // `kv_separator`.
+    pub(crate) fn validate_separated(&self, key: &str, record_separator: &str, kv_separator: &str) -> Result<(), DBError> {
+        let invalid = |reason: String| Err(DBError::InvalidKey { key: key.to_string(), reason });
+        if key.is_empty() && !self.allow_empty {
+            return invalid("key is empty".to_string());
+        }
+        if let Some(max_len) = self.max_len && key.len() > max_len {
+            return invalid(format!("key is longer than {} bytes", max_len));
+        }
+        if let Some(allowed) = self.allowed_char && let Some(c) = key.chars().find(|c| !allowed(*c)) {
+            return invalid(format!("key contains disallowed character {:?}", c));
+        }
+        if self.reject_separators && let Some(separator) = [kv_separator, record_separator].into_iter().find(|separator| key.contains(separator)) {
+            return invalid(format!("key contains the separator {:?}", separator));
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeyValidator {
+    fn default() -> Self {
+        KeyValidator { max_len: None, allow_empty: false, allowed_char: Some(|c| c != '\n' && c != '\r'), reject_separators: false }
+    }
+}
+
+/// Which backups survive pruning after a save. Ages are measured from the backup's timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupPolicy {
+    /// Keep the newest `n` backups. `KeepCount(0)` disables backups entirely.
+    KeepCount(usize),
+    /// Keep every backup younger than the given age, however many there are.
+    KeepWithin(TimeDelta),
+    /// Keep a backup if it's among the newest `n` or younger than the given age.
+    KeepCountOrWithin(usize, TimeDelta),
+}
+
+impl BackupPolicy {
+    fn takes_backups(&self) -> bool {
+        *self != BackupPolicy::KeepCount(0)
+    }
+
+    // `rank` counts from the newest backup, which has rank 0.
+    fn keeps(&self, rank: usize, age: TimeDelta) -> bool {
+        match *self {
+            BackupPolicy::KeepCount(n) => rank < n,
+            BackupPolicy::KeepWithin(within) => age < within,
+            BackupPolicy::KeepCountOrWithin(n, within) => rank < n || age < within,
+        }
     }
 }
 
-impl std::error::Error for DBError {}
+/// What a save does when it can't take or prune a backup, e.g. because `backups/` is read-only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackupMode {
+    /// Fail the save and leave the data file untouched.
+    #[default]
+    Required,
+    /// Log a warning and write the data file anyway, without a backup of what it replaces.
+    BestEffort,
+}
+
+/// Tunables for `save_db_with_options`. `SaveOptions::default()` matches `save_db`.
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    /// Which backups to keep under `backups/`; the rest are pruned on save.
+    pub backup_policy: BackupPolicy,
+    /// Whether a failure to take or prune a backup fails the save.
+    pub backup: BackupMode,
+    /// Whether to fsync the data file and directory before returning.
+    pub fsync: bool,
+    /// Where backups are written and pruned. Defaults to a `backups` directory inside the
+    /// database directory.
+    pub backup_dir: Option<PathBuf>,
+    /// Gzip the data file (and therefore its backups). Loading detects compression itself, so
+    /// this only affects how the file is written.
+    pub compress: bool,
+    /// How long to wait for another saver to release the database's lock file before giving up.
+    pub lock_timeout: Duration,
+    /// Take a backup even when the new contents are identical to what's already on disk.
+    pub force_backup: bool,
+    /// Write records in key order, so saving the same data always produces the same file and
+    /// version-controlled databases diff cleanly.
+    pub sorted: bool,
+    /// Like `sorted`, and also sort the keys of every map and struct within each value, so the
+    /// same logical data always saves to the same bytes, whatever order it was inserted in.
+    /// Values are converted to a `serde_json::Value` along the way, which costs a copy of each.
+    pub canonical: bool,
+    /// Append a CRC32 of each serialized value to its line, as `key=value#crc`. Loading verifies
+    /// them and fails with `DBError::ChecksumMismatch` on a corrupted record.
+    pub checksums: bool,
+    /// Write values as indented, multi-line JSON, for files that people read and edit. Each line
+    /// after a record's first is indented, and a `#memory_db:pretty` header tells the loader to
+    /// treat indented lines as continuations rather than records of their own. Pretty files are
+    /// bigger and slower to save, and line-oriented tools like `grep` only see part of a record.
+    /// Only codecs that implement `Codec::encode_pretty` produce multi-line values.
+    pub pretty: bool,
+    /// Ends every record, `\n` by default. With anything else, values may contain line breaks,
+    /// but the file can only be read back by `load_db_with` with the same separators.
+    pub record_separator: String,
+    /// Separates each key from its value, `=` by default. Neither separator may contain `%`, `#`
+    /// or hex digits, which key escapes and checksums use.
+    pub kv_separator: String,
+    /// Checked against every key before anything is written.
+    pub key_validator: KeyValidator,
+    /// Record this schema version in a `#schema=N` header, for `load_db_migrated`. `None` keeps
+    /// the version the file on disk already declares, if any.
+    pub schema_version: Option<u32>,
+    /// Where saves get the time that names new backups and that pruning measures ages from.
+    /// Defaults to the local time; `|| chrono::Utc::now().fixed_offset()` names backups in UTC.
+    pub clock: fn() -> DateTime<FixedOffset>,
+    /// Checked against every value before anything is written. `None`, the default, skips the
+    /// check.
+    pub json_schema: Option<JsonSchema>,
+}
+
+fn local_now() -> DateTime<FixedOffset> {
+    chrono::Local::now().fixed_offset()
+}
 
-impl From<serde_json::Error> for DBError {
-    fn from(e: serde_json::Error) -> Self {
-        DBError(format!("Serde error: {}", e))
+impl SaveOptions {
+    fn resolve_backup_dir(&self, path: &str) -> PathBuf {
+        self.backup_dir.clone().unwrap_or_else(|| get_backup_dir(path))
     }
 }
 
-impl From<std::io::Error> for DBError {
-    fn from(e: std::io::Error) -> Self {
-        DBError(format!("IO error: {}", e))
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions {
+            backup_policy: BackupPolicy::KeepCount(MAX_BACKUPS),
+            backup: BackupMode::Required,
+            fsync: true,
+            backup_dir: None,
+            compress: false,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            force_backup: false,
+            sorted: false,
+            canonical: false,
+            checksums: false,
+            pretty: false,
+            record_separator: "\n".to_string(),
+            kv_separator: "=".to_string(),
+            key_validator: KeyValidator::default(),
+            schema_version: None,
+            clock: local_now,
+            json_schema: None,
+        }
     }
 }
 
+// An empty path means the current directory, rather than the filesystem root that
+// "/memory.db" would point at.
+fn get_db_dir(path: &str) -> &str {
+    if path.is_empty() { "." } else { path }
+}
+
 fn get_db_path(path: &str) -> String {
-    return format!("{}/memory.db", path);
+    format!("{}/memory.db", get_db_dir(path))
 }
 
+// A temp file name no other save will use: the process id keeps processes apart, and the
+// counter keeps saves within one process apart.
 fn get_tmp_path(path: &str) -> String {
-    return format!("{}/memory.db.tmp", path);
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+    format!("{}/memory.db.{}-{}.tmp", get_db_dir(path), std::process::id(), SAVES.fetch_add(1, Ordering::Relaxed))
+}
+
+fn get_wal_path(path: &str) -> String {
+    format!("{}/memory.db.wal", get_db_dir(path))
+}
+
+// Backups directory lives alongside the DB file, under "<db_dir>/backups".
+fn get_backup_dir(path: &str) -> PathBuf {
+    Path::new(get_db_dir(path)).join("backups")
+}
+
+// Creates the database directory, and any missing parents, so first-run saves just work.
+fn ensure_db_dir(path: &str) -> Result<(), DBError> {
+    fs::create_dir_all(get_db_dir(path))?;
+    Ok(())
 }
 
+/// Loads the database at `path`. A missing file loads as an empty database, but any other
+/// read error, and any non-blank line without a `=`, is returned as an error.
+///
+/// If a key appears on more than one line, the last line wins. Lines whose first non-blank
+/// character is `#` are comments and, like blank lines, are skipped; saving doesn't preserve them.
 pub fn load_db<T>(path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
-    let contents: String = fs::read_to_string(get_db_path(path)).unwrap_or_default();
+    load_with(path, false, &JsonCodec)
+}
+
+/// Like `load_db`, but decodes values with `codec` instead of JSON.
+pub fn load_db_with_codec<T, C>(path: &str, codec: &C) -> Result<DB<T>, DBError> where T: DeserializeOwned, C: Codec {
+    load_with(path, false, codec)
+}
+
+/// Like `load_db`, but parses the lines read from `reader` instead of a file. `reader` must
+/// yield the plain text format, as written by `write_db`; compressed and binary data isn't
+/// detected.
+pub fn read_db<T, R>(mut reader: R) -> Result<DB<T>, DBError> where T: DeserializeOwned, R: Read {
+    let mut contents: String = String::new();
+    reader.read_to_string(&mut contents)?;
+    parse_db(contents.strip_prefix('\u{feff}').unwrap_or(&contents), false, &JsonCodec)
+}
+
+/// Like `load_db`, but silently skips lines that aren't `key=value` records.
+pub fn load_db_lenient<T>(path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    load_with(path, true, &JsonCodec)
+}
+
+/// A record that `load_db_with_fallback` left out: its value didn't deserialize, and the
+/// fallback returned `None` for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedLine {
+    /// 1-based line number within the file.
+    pub line: usize,
+    pub key: String,
+    /// The value as written in the file.
+    pub value: String,
+    /// The deserialization error, as displayed.
+    pub error: String,
+}
+
+/// Like `load_db`, but a value that doesn't deserialize as `T` is passed to `on_error`, along
+/// with its key and raw JSON, instead of failing the load. Whatever `on_error` returns is used
+/// in its place; `None` skips the record, as if the line weren't there, and reports it in the
+/// returned list. This is meant for files written with an older version of `T`.
+///
+/// Lines that aren't `key=value` records at all are still an error.
+pub fn load_db_with_fallback<T, F>(path: &str, on_error: F) -> Result<(DB<T>, Vec<SkippedLine>), DBError> where T: DeserializeOwned, F: Fn(&str, &str, serde_json::Error) -> Option<T> {
+    let contents = read_db_file(path)?;
+    let mut db: DB<T> = HashMap::new();
+    let mut skipped: Vec<SkippedLine> = Vec::new();
+    for record in records(&contents, false) {
+        let record = record?;
+        let value: Option<T> = match serde_json::from_str(record.value) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let error: String = e.to_string();
+                let recovered: Option<T> = on_error(&record.key, record.value, e);
+                if recovered.is_none() {
+                    skipped.push(SkippedLine { line: record.line, key: record.key.clone(), value: record.value.to_string(), error });
+                }
+                recovered
+            }
+        };
+        if let Some(value) = value {
+            db.insert(record.key, value);
+        }
+    }
+    Ok((db, skipped))
+}
+
+/// A line that `load_db_report` couldn't load.
+#[derive(Debug)]
+pub struct LineError {
+    /// 1-based line number within the file.
+    pub line_number: usize,
+    /// The line as written in the file, continuation lines included in a pretty file.
+    pub content: String,
+    /// Why it failed: a malformed line, a bad key encoding, a checksum mismatch, or a value
+    /// that didn't deserialize as `T`.
+    pub error: DBError,
+}
+
+/// Like `load_db`, but loads every record it can and reports the rest instead of failing on
+/// the first bad line, for recovering what's left of a damaged file. A file that can't be read
+/// at all is still an error.
+pub fn load_db_report<T>(path: &str) -> Result<(DB<T>, Vec<LineError>), DBError> where T: DeserializeOwned {
+    let contents = read_db_file(path)?;
+    let checksummed: bool = has_checksums(&contents);
+    let mut db: DB<T> = HashMap::new();
+    let mut errors: Vec<LineError> = Vec::new();
+    for (index, line) in logical_lines(&contents, is_pretty(contents.lines())) {
+        let entry = parse_line(index, line, false, checksummed).map(|record| record.and_then(|record| Ok((record.key, serde_json::from_str::<T>(record.value)?))));
+        match entry {
+            Some(Ok((key, value))) => {
+                db.insert(key, value);
+            }
+            Some(Err(error)) => errors.push(LineError { line_number: index + 1, content: line.to_string(), error }),
+            None => {}
+        }
+    }
+    Ok((db, errors))
+}
+
+// Reads the file a line at a time rather than into one string, so only the map itself and the
+// line being parsed are in memory at once.
+fn load_with<T, C>(path: &str, lenient: bool, codec: &C) -> Result<DB<T>, DBError> where T: DeserializeOwned, C: Codec {
+    let mut db: DB<T> = HashMap::new();
+    for entry in stream::stream_with(path, lenient, codec) {
+        let (key, value) = entry?;
+        db.insert(key, value);
+    }
+    let file_path: String = get_db_path(path);
+    let bytes: u64 = fs::metadata(&file_path).map_or(0, |metadata| metadata.len());
+    log::debug!("loaded {} entries ({} bytes) from {}", db.len(), bytes, file_path);
+    Ok(db)
+}
+
+/// Like `load_db`, but a key that appears on more than one line is an error instead of the
+/// last line winning.
+pub fn load_db_strict<T>(path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let contents = read_db_file(path)?;
+    let mut lines_by_key: HashMap<String, Vec<usize>> = HashMap::new();
+    for record in records(&contents, false) {
+        let record = record?;
+        lines_by_key.entry(record.key).or_default().push(record.line);
+    }
+    let mut duplicates: Vec<(String, Vec<usize>)> = lines_by_key.into_iter()
+        .filter(|(_, lines)| lines.len() > 1)
+        .collect();
+    // Report the duplicate that appears first in the file, so the error is deterministic.
+    duplicates.sort_by_key(|(_, lines)| lines[0]);
+    if let Some((key, lines)) = duplicates.into_iter().next() {
+        return Err(DBError::DuplicateKey { key, lines });
+    }
+    parse_db(&contents, false, &JsonCodec)
+}
+
+fn read_db_file(path: &str) -> Result<String, DBError> {
+    match read_file_contents(Path::new(&get_db_path(path))) {
+        Ok(contents) => Ok(contents),
+        Err(DBError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// Reads a data or backup file, transparently decompressing it if it starts with the gzip magic
+// bytes, so compressed and plain files load the same way.
+fn read_file_bytes(file_path: &Path) -> std::io::Result<Vec<u8>> {
+    decompress(fs::read(file_path)?)
+}
+
+fn decompress(bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed)?;
+        return Ok(decompressed);
+    }
+    Ok(bytes)
+}
+
+fn read_file_contents(file_path: &Path) -> Result<String, DBError> {
+    text_contents(read_file_bytes(file_path)?, file_path)
+}
+
+// The text of a decompressed file, refusing the formats that aren't text. `file_path` is only
+// used in errors.
+fn text_contents(mut bytes: Vec<u8>, file_path: &Path) -> Result<String, DBError> {
+    if bytes.starts_with(BINARY_MAGIC) {
+        return Err(DBError::WrongFormat(format!("{} is a binary database; use load_db_binary", file_path.display())));
+    }
+    if bytes.starts_with(ENCRYPTED_MAGIC) {
+        return Err(DBError::WrongFormat(format!("{} is an encrypted database; use load_db_encrypted", file_path.display())));
+    }
+    if bytes.starts_with(UTF8_BOM) {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    String::from_utf8(bytes).map_err(|e| DBError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+}
+
+/// Loads the backup named `backup_name` from the `backups/` directory under `path`, parsing it
+/// the same way `load_db` parses the main file.
+pub fn restore_from_backup<T>(path: &str, backup_name: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let backup_path = get_backup_dir(path).join(backup_name);
+    let contents = match read_file_contents(&backup_path) {
+        Ok(contents) => contents,
+        Err(DBError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(DBError::NotFound(format!("backup {}", backup_name)));
+        }
+        Err(e) => return Err(e),
+    };
+    parse_db(&contents, false, &JsonCodec)
+}
+
+/// Loads the most recent backup under `path`, ordered by the timestamp in its file name.
+pub fn restore_latest_backup<T>(path: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    match read_backups(&get_backup_dir(path))?.0.last() {
+        Some((_, latest)) => restore_from_backup(path, latest),
+        None => Err(DBError::NotFound(format!("any backup in {}", get_backup_dir(path).display()))),
+    }
+}
+
+/// Like `load_db`, but if the main file is corrupt, falls back to the newest backup that still
+/// parses. Alongside the data it returns the timestamp of the backup that was used, or `None` if
+/// the main file loaded fine. If no backup parses either, the main file's error is returned.
+///
+/// Only parse errors trigger recovery; I/O errors are returned as-is.
+pub fn load_db_or_recover<T>(path: &str) -> Result<(DB<T>, Option<DateTime<FixedOffset>>), DBError> where T: DeserializeOwned {
+    let err = match load_db(path) {
+        Ok(db) => return Ok((db, None)),
+        Err(e) if is_corruption(&e) => e,
+        Err(e) => return Err(e),
+    };
+    for (timestamp, name) in read_backups(&get_backup_dir(path))?.0.iter().rev() {
+        match restore_from_backup(path, name) {
+            Ok(db) => return Ok((db, Some(*timestamp))),
+            Err(e) if is_corruption(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(err)
+}
+
+fn is_corruption(err: &DBError) -> bool {
+    matches!(
+        err,
+        DBError::Serde(_) | DBError::MalformedLine { .. } | DBError::InvalidKeyEncoding(_) | DBError::ChecksumMismatch { .. }
+    )
+}
+
+/// Reads a single key without deserializing the rest of the file. Later lines win over earlier
+/// ones for the same key, exactly as in `load_db`.
+pub fn get_one<T>(path: &str, key: &str) -> Result<Option<T>, DBError> where T: DeserializeOwned {
+    let contents = read_db_file(path)?;
+    let mut found: Option<&str> = None;
+    for record in records(&contents, false) {
+        let record = record?;
+        if record.key == key {
+            found = Some(record.value);
+        }
+    }
+    match found {
+        Some(raw) => Ok(Some(serde_json::from_str(raw)?)),
+        None => Ok(None),
+    }
+}
+
+/// Loads only the entries whose key starts with `prefix`, e.g. `"user:"`. Keys are matched
+/// before any value is deserialized, so non-matching entries cost only a line scan.
+pub fn scan_prefix<T>(path: &str, prefix: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    load_matching(path, |key| key.starts_with(prefix))
+}
+
+/// Loads only the entries whose key falls in the half-open range `start..end`, comparing keys
+/// as strings.
+pub fn scan_range<T>(path: &str, start: &str, end: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    load_matching(path, |key| start <= key && key < end)
+}
+
+/// Up to `limit` entries starting at the `offset`-th, in key order, so that consecutive pages
+/// cover every entry exactly once as long as the database doesn't change in between. Only the
+/// values on the page are deserialized, but every key is read and sorted on each call.
+pub fn page<T>(path: &str, offset: usize, limit: usize) -> Result<Vec<(String, T)>, DBError> where T: DeserializeOwned {
+    let contents = read_db_file(path)?;
+    let mut raw: HashMap<String, &str> = HashMap::new();
+    for record in records(&contents, false) {
+        let record = record?;
+        raw.insert(record.key, record.value);
+    }
+    let mut raw: Vec<(String, &str)> = raw.into_iter().collect();
+    raw.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    raw.into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(key, value)| Ok((key, serde_json::from_str(value)?)))
+        .collect()
+}
+
+/// Whether `key` is in the database, found by decoding keys line by line without deserializing
+/// any value. Stops at the first match, so malformed lines after it aren't reported.
+pub fn contains_key(path: &str, key: &str) -> Result<bool, DBError> {
+    let contents = read_db_file(path)?;
+    for record in records(&contents, false) {
+        if record?.key == key {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Counts the entries `load_db` would return, without deserializing any values. A key repeated
+/// on several lines counts once, and a malformed line is an error, just as when loading.
+pub fn count_entries(path: &str) -> Result<usize, DBError> {
+    let contents = read_db_file(path)?;
+    let mut keys: HashSet<String> = HashSet::new();
+    for record in records(&contents, false) {
+        keys.insert(record?.key);
+    }
+    Ok(keys.len())
+}
+
+/// Loads only the entries whose value satisfies `predicate`. Unlike `scan_prefix` this has to
+/// deserialize every value, so it's a full O(n) scan of the file.
+pub fn query<T, F>(path: &str, predicate: F) -> Result<DB<T>, DBError> where T: DeserializeOwned, F: Fn(&T) -> bool {
+    let mut db: DB<T> = load_db(path)?;
+    db.retain(|_, value| predicate(value));
+    Ok(db)
+}
+
+fn load_matching<T, F>(path: &str, matches: F) -> Result<DB<T>, DBError> where T: DeserializeOwned, F: Fn(&str) -> bool {
+    let contents = read_db_file(path)?;
+    let mut raw: HashMap<String, &str> = HashMap::new();
+    for record in records(&contents, false) {
+        let record = record?;
+        if matches(&record.key) {
+            raw.insert(record.key, record.value);
+        }
+    }
+    let mut db: DB<T> = HashMap::with_capacity(raw.len());
+    for (key, value) in raw {
+        db.insert(key, serde_json::from_str(value)?);
+    }
+    Ok(db)
+}
+
+fn parse_db<T, C>(contents: &str, lenient: bool, codec: &C) -> Result<DB<T>, DBError> where T: DeserializeOwned, C: Codec {
     let mut db: HashMap<String, T> = HashMap::new();
-    for line in contents.lines() {
-        let kv_option: Option<(&str, &str)> = line.split_once('=');
-        if let Some((k, v)) = kv_option {
-            let value: T = serde_json::from_str(v.trim())?;
-            db.insert(k.trim().to_string(), value);
+    for record in records(contents, lenient) {
+        let record = record?;
+        let value: T = codec.decode(record.value)?;
+        db.insert(record.key, value);
+    }
+    Ok(db)
+}
+
+// A `key=value` line with its key decoded and its value left as unparsed JSON, so callers can
+// deserialize only what they need.
+struct Record<'a> {
+    // 1-based line number within the file.
+    line: usize,
+    key: String,
+    // The key as written in the file, before percent-decoding.
+    raw_key: &'a str,
+    value: &'a str,
+}
+
+fn records(contents: &str, lenient: bool) -> impl Iterator<Item = Result<Record<'_>, DBError>> {
+    let checksummed = has_checksums(contents);
+    let lines = logical_lines(contents, is_pretty(contents.lines()));
+    lines.filter_map(move |(index, line)| parse_line(index, line, lenient, checksummed))
+}
+
+// The file's lines with their 0-based indices. In a pretty file, lines that start with
+// whitespace continue the line above, and are joined to it into one slice of `contents`; keys
+// that start with whitespace are always escaped, so a record's first line never does.
+fn logical_lines(contents: &str, pretty: bool) -> impl Iterator<Item = (usize, &str)> {
+    let offset = move |line: &str| line.as_ptr() as usize - contents.as_ptr() as usize;
+    let mut lines = contents.lines().enumerate().peekable();
+    std::iter::from_fn(move || {
+        let (index, first) = lines.next()?;
+        let start: usize = offset(first);
+        let mut end: usize = start + first.len();
+        while pretty && let Some((_, next)) = lines.next_if(|(_, line)| is_continuation(line)) {
+            end = offset(next) + next.len();
         }
-        
+        Some((index, &contents[start..end]))
+    })
+}
+
+fn is_continuation(line: &str) -> bool {
+    line.starts_with([' ', '\t'])
+}
+
+// The pretty header comes first, or second after the checksum header.
+fn is_pretty<I, S>(mut lines: I) -> bool where I: Iterator<Item = S>, S: AsRef<str> {
+    let mut line = lines.next();
+    if line.as_ref().is_some_and(|line| line.as_ref() == CHECKSUM_HEADER) {
+        line = lines.next();
     }
-    return Ok(db);
+    line.is_some_and(|line| line.as_ref() == PRETTY_HEADER)
 }
 
-pub fn save_db<T>(path: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize {
-    delete_old_backups(path)?;
-    let temp_path  = get_tmp_path(path);
-    let file_path = get_db_path(path);
+// Classifies one line, `index` being 0-based: `None` for blanks, comments and (when lenient)
+// malformed lines, which are all skipped.
+fn parse_line(index: usize, line: &str, lenient: bool, checksummed: bool) -> Option<Result<Record<'_>, DBError>> {
+    parse_record(index, line, lenient, checksummed, "=")
+}
 
-    // Ensure directory for DB file exists, if any.
-    if !fs::exists(Path::new(&path))? {
-        fs::create_dir_all(Path::new(&path))?;
+// `parse_line` for a record whose key and value are separated by `kv_separator`.
+fn parse_record<'a>(index: usize, line: &'a str, lenient: bool, checksummed: bool, kv_separator: &str) -> Option<Result<Record<'a>, DBError>> {
+    let split = split_record(index, line, lenient, checksummed, kv_separator)?;
+    Some(split.and_then(|(raw_key, value)| Ok(Record { line: index + 1, key: decode_key(raw_key)?, raw_key, value })))
+}
+
+// `parse_record` short of decoding the key: the key as written and the value, checksum removed.
+fn split_record<'a>(index: usize, line: &'a str, lenient: bool, checksummed: bool, kv_separator: &str) -> Option<Result<(&'a str, &'a str), DBError>> {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
     }
+    match line.split_once(kv_separator) {
+        Some((k, v)) if checksummed => Some(match strip_checksum(v.trim()) {
+            Some(value) => Ok((k.trim(), value)),
+            None => decode_key(k.trim()).and_then(|key| Err(DBError::ChecksumMismatch { key })),
+        }),
+        Some((k, v)) => Some(Ok((k.trim(), v.trim()))),
+        None if lenient => None,
+        None => Some(Err(DBError::MalformedLine { line: index + 1, content: line.to_string() })),
+    }
+}
+
+fn has_checksums(contents: &str) -> bool {
+    contents.lines().next() == Some(CHECKSUM_HEADER)
+}
+
+fn value_checksum(value: &str) -> String {
+    format!("{:08x}", crc32fast::hash(value.as_bytes()))
+}
 
-    fs::File::create(&temp_path)?;
-    if !(fs::exists(&file_path)?) {
-        fs::File::create(&file_path)?;
+// Splits the `#crc` suffix off a checksummed value and checks it, returning `None` on a
+// mismatch. A missing suffix counts as one, since it means the line was cut short.
+fn strip_checksum(value: &str) -> Option<&str> {
+    match value.rsplit_once('#') {
+        Some((value, checksum)) if checksum == value_checksum(value) => Some(value),
+        _ => None,
     }
-    // Backups directory lives alongside the DB file, under "<db_dir>/backups".
-    let backup_dir: PathBuf = Path::new(path).join("backups");
-    if !fs::exists(&backup_dir)? {
-        fs::create_dir_all(&backup_dir)?;
+}
+
+/// Saves `contents`, flushing the data file and its directory to disk before returning.
+pub fn save_db<T>(path: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize {
+    save_db_with_options(path, contents, &SaveOptions::default())
+}
+
+/// Like `save_db`, but skips the fsync calls. Faster, at the cost of possibly losing the
+/// latest save if the machine loses power shortly afterwards.
+pub fn save_db_no_fsync<T>(path: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize {
+    save_db_with_options(path, contents, &SaveOptions { fsync: false, ..SaveOptions::default() })
+}
+
+/// Like `save_db`, but writes records in key order. See `SaveOptions::sorted`.
+pub fn save_db_sorted<T>(path: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize {
+    save_db_with_options(path, contents, &SaveOptions { sorted: true, ..SaveOptions::default() })
+}
+
+/// Writes the same lines `save_db` would to `writer`, e.g. a socket or an in-memory buffer. There
+/// is no file, so none of the lock, backups, temp file or fsync apply.
+pub fn write_db<T, W>(writer: &mut W, contents: &DB<T>) -> Result<(), DBError> where T: Serialize, W: Write {
+    let options = SaveOptions::default();
+    validate_keys(contents, &options)?;
+    write_records(writer, contents, &options, &JsonCodec)?;
+    Ok(())
+}
+
+/// What a save did, as returned by `save_db_reporting`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveReport {
+    /// The backup of the previous contents, or `None` if none was taken: on the first save, when
+    /// the contents didn't change, or when the backup policy keeps none.
+    pub backup_path: Option<PathBuf>,
+    /// The size of the new data file, in bytes.
+    pub bytes_written: usize,
+    pub entries: usize,
+}
+
+/// Like `save_db`, but reports the backup it took and what it wrote.
+pub fn save_db_reporting<T>(path: &str, contents: &DB<T>) -> Result<SaveReport, DBError> where T: Serialize {
+    save_with_reporting(path, contents, &SaveOptions::default(), &JsonCodec)
+}
+
+pub fn save_db_with_options<T>(path: &str, contents: &DB<T>, options: &SaveOptions) -> Result<(), DBError> where T: Serialize {
+    save_with(path, contents, options, &JsonCodec)
+}
+
+/// Like `save_db`, but encodes values with `codec` instead of JSON.
+pub fn save_db_with_codec<T, C>(path: &str, contents: &DB<T>, codec: &C) -> Result<(), DBError> where T: Serialize, C: Codec {
+    save_with(path, contents, &SaveOptions::default(), codec)
+}
+
+/// The value under `key`, first saving `T::default()` under it if there isn't one. The check and
+/// the save happen under the database's lock, so concurrent callers agree on the value.
+pub fn get_or_default<T>(path: &str, key: &str) -> Result<T, DBError> where T: Default + Serialize + DeserializeOwned {
+    KeyValidator::default().validate(key)?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let mut db: DB<T> = load_db(path)?;
+    if let Some(value) = db.remove(key) {
+        return Ok(value);
     }
-    let backup_path = backup_dir.join(chrono::Local::now().to_rfc3339());
-    fs::copy(&file_path, &backup_path)?;
-    let mut temp_file = fs::OpenOptions::new().write(true).create(true).append(true).open(&temp_path)?;
-    for (key,value) in contents {
-        temp_file.write(format!("{}={}\n", key, serde_json::to_string(value)?).as_bytes())?;
+    db.insert(key.to_string(), T::default());
+    save_locked(path, &db, &options, &JsonCodec)?;
+    Ok(T::default())
+}
+
+/// Sets `key` to `new` only if its current value equals `expected`, where `None` means the key
+/// must be absent. Returns whether the swap happened. The database's lock is held from the read
+/// through the write, so concurrent swaps can't both succeed against the same value.
+///
+/// Compression and checksums of the existing file are kept.
+pub fn compare_and_swap<T>(path: &str, key: &str, expected: Option<&T>, new: T) -> Result<bool, DBError> where T: PartialEq + Serialize + DeserializeOwned {
+    KeyValidator::default().validate(key)?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let mut db: DB<T> = load_db(path)?;
+    if db.get(key) != expected {
+        return Ok(false);
     }
-    fs::copy(&temp_path, &file_path)?;
-    fs::remove_file(temp_path)?;
+    db.insert(key.to_string(), new);
+    save_locked(path, &db, &options, &JsonCodec)?;
+    Ok(true)
+}
+
+/// Applies `partial` to the value stored under `key` as a JSON merge patch (RFC 7386): fields of
+/// `partial` replace the matching fields of the value, nested objects are merged recursively,
+/// and fields set to `null` are removed. Fields `partial` doesn't mention are left untouched.
+/// Fails with `DBError::NotFound` if the key is absent.
+///
+/// As the RFC specifies, an object patch applied to a value that isn't an object replaces it
+/// with the patch (minus its `null` fields), and a patch that isn't an object replaces the value
+/// outright. The lock is held from the read through the write, and the existing file's
+/// compression and checksums are kept.
+pub fn patch(path: &str, key: &str, partial: serde_json::Value) -> Result<(), DBError> {
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let mut db: DB<Box<RawValue>> = load_db(path)?;
+    let Some(raw) = db.get_mut(key) else {
+        return Err(DBError::NotFound(format!("key {}", key)));
+    };
+    let mut value: serde_json::Value = serde_json::from_str(raw.get())?;
+    merge_patch(&mut value, partial);
+    *raw = serde_json::value::to_raw_value(&value)?;
+    save_locked(path, &db, &options, &JsonCodec)?;
     Ok(())
 }
 
-fn delete_old_backups(db_path: &str) -> Result<(), std::io::Error> {
-    let db_path = Path::new(db_path);
-    let backup_dir: PathBuf = db_path.join("backups");
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(fields) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (name, value) in fields {
+            if value.is_null() {
+                target.remove(&name);
+            } else {
+                merge_patch(target.entry(name).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
+}
 
-    if !backup_dir.exists() {
-        return Ok(());
+/// Moves the value stored under `old` to `new`, returning whether `old` was present. Renaming
+/// onto a key that already exists fails with `DBError::KeyExists` and changes nothing, so a
+/// rename never silently drops a value. Values are moved verbatim, without deserializing them.
+pub fn rename_key(path: &str, old: &str, new: &str) -> Result<bool, DBError> {
+    KeyValidator::default().validate(new)?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let mut db: DB<Box<RawValue>> = load_db(path)?;
+    if !rename_entry(&mut db, old, new)? {
+        return Ok(false);
+    }
+    save_locked(path, &db, &options, &JsonCodec)?;
+    Ok(true)
+}
+
+// The in-memory half of `rename_key`, shared with `Database::rename`.
+fn rename_entry<T>(db: &mut DB<T>, old: &str, new: &str) -> Result<bool, DBError> {
+    if !db.contains_key(old) {
+        return Ok(false);
     }
+    if old != new && db.contains_key(new) {
+        return Err(DBError::KeyExists(new.to_string()));
+    }
+    if let Some(value) = db.remove(old) {
+        db.insert(new.to_string(), value);
+    }
+    Ok(true)
+}
+
+/// What `transform_keys_with` does when several keys map to the same new key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyCollision {
+    /// Fail with `DBError::KeyExists` and change nothing.
+    #[default]
+    Error,
+    /// Keep the value whose original key sorts last.
+    LastWins,
+}
+
+/// Replaces every key with `f(key)`, the bulk counterpart to `rename_key`. Fails with
+/// `DBError::KeyExists` if two keys map to the same new key, leaving the file untouched; see
+/// `transform_keys_with` to resolve those instead. The current contents are backed up first.
+pub fn transform_keys<F>(path: &str, f: F) -> Result<(), DBError> where F: Fn(&str) -> String {
+    transform_keys_with(path, f, KeyCollision::Error)
+}
+
+/// Like `transform_keys`, resolving collisions as `on_collision` says.
+pub fn transform_keys_with<F>(path: &str, f: F, on_collision: KeyCollision) -> Result<(), DBError> where F: Fn(&str) -> String {
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = SaveOptions { force_backup: true, ..options_preserving_format(path)? };
+    let db: DB<Box<RawValue>> = load_db(path)?;
+    // Sorted, so that "last" means the same thing on every run.
+    let mut entries: Vec<(String, Box<RawValue>)> = db.into_iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let validator = KeyValidator::default();
+    let mut transformed: DB<Box<RawValue>> = DB::with_capacity(entries.len());
+    for (key, value) in entries {
+        let new_key: String = f(&key);
+        validator.validate(&new_key)?;
+        if on_collision == KeyCollision::Error && transformed.contains_key(&new_key) {
+            return Err(DBError::KeyExists(new_key));
+        }
+        transformed.insert(new_key, value);
+    }
+    save_locked(path, &transformed, &options, &JsonCodec)?;
+    Ok(())
+}
+
+/// Empties the database, first backing up the current contents so they can be brought back
+/// with `restore_from_backup` or `restore_latest_backup`.
+pub fn clear_db(path: &str) -> Result<(), DBError> {
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = SaveOptions { force_backup: true, ..options_preserving_format(path)? };
+    save_locked(path, &DB::<()>::new(), &options, &JsonCodec)?;
+    Ok(())
+}
 
-    let backup_path = backup_dir.as_path();
-    let paths = fs::read_dir(&backup_dir)?;
-    let mut file_names: Vec<DateTime<FixedOffset>> = Vec::new();
-    for path_result in paths {
-        match path_result {
-            Ok(path) => file_names.push(
-                DateTime::parse_from_rfc3339(path.file_name().to_str().unwrap()).unwrap()
-            ),
-            Err(_) => {}
+// Default options, except that compression, checksums, pretty printing and the schema version
+// follow the existing file, for operations that rewrite a database on the caller's behalf. Keys
+// already in the file were accepted when they were saved, so they aren't validated again; callers
+// check any new key. The caller must hold the database's `FileLock`, so that a concurrent save
+// can't change the format between this read and the rewrite.
+fn options_preserving_format(path: &str) -> Result<SaveOptions, DBError> {
+    let file_path: String = get_db_path(path);
+    let bytes: Vec<u8> = match fs::read(&file_path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let compress: bool = bytes.starts_with(&GZIP_MAGIC);
+    let contents: String = text_contents(decompress(bytes)?, Path::new(&file_path))?;
+    let checksums: bool = has_checksums(&contents);
+    let pretty: bool = is_pretty(contents.lines());
+    let schema_version: Option<u32> = schema::parse_schema_version(contents.lines())?;
+    Ok(SaveOptions { compress, checksums, pretty, schema_version, key_validator: KeyValidator::permissive(), ..SaveOptions::default() })
+}
+
+/// Writes `entries` straight to disk as the database's new contents, without collecting them into
+/// a map first. If a key repeats, the last entry wins on load, as with duplicate lines in any
+/// file; the duplicates stay in the file, though, so `load_db_strict` rejects it until the next
+/// full save. Compression, checksums, pretty printing and the schema version of the existing file
+/// are kept.
+pub fn save_all<T, I>(path: &str, entries: I) -> Result<(), DBError> where T: Serialize, I: IntoIterator<Item = (String, T)> {
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let validator = KeyValidator::default();
+    commit(path, &options, |writer| {
+        write_headers(writer, &options)?;
+        let mut count: usize = 0;
+        let mut line: String = String::new();
+        for (key, value) in entries {
+            validator.validate(&key)?;
+            let encoded: String = if options.pretty {
+                encode_pretty_value(&JsonCodec, &key, &value)?
+            } else {
+                encode_value(&JsonCodec, &key, &value)?
+            };
+            line.clear();
+            push_record(&mut line, &key, &encoded, options.checksums);
+            writer.write_all(line.as_bytes())?;
+            count += 1;
         }
+        Ok(count)
+    })
+}
+
+pub(crate) fn save_with<T, C>(path: &str, contents: &DB<T>, options: &SaveOptions, codec: &C) -> Result<(), DBError> where T: Serialize, C: Codec {
+    save_with_reporting(path, contents, options, codec)?;
+    Ok(())
+}
+
+pub(crate) fn save_with_reporting<T, C>(path: &str, contents: &DB<T>, options: &SaveOptions, codec: &C) -> Result<SaveReport, DBError> where T: Serialize, C: Codec {
+    options.check_separators()?;
+    validate_keys(contents, options)?;
+    json_schema::validate_values(contents, options.json_schema.as_ref())?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, options.lock_timeout)?;
+    save_locked(path, contents, options, codec)
+}
+
+fn validate_keys<T>(contents: &DB<T>, options: &SaveOptions) -> Result<(), DBError> {
+    for key in contents.keys() {
+        options.key_validator.validate_separated(key, &options.record_separator, &options.kv_separator)?;
     }
-    file_names.sort();
+    Ok(())
+}
 
-    let backups_to_delete = file_names.len().saturating_sub(MAX_BACKUPS);
-    for entry in file_names.iter().take(backups_to_delete) {
-        let file_path = backup_path.join(entry.to_rfc3339());
-        fs::remove_file(file_path)?;
+// Performs the save proper; the caller must hold the database's `FileLock`.
+fn save_locked<T, C>(path: &str, contents: &DB<T>, options: &SaveOptions, codec: &C) -> Result<SaveReport, DBError> where T: Serialize, C: Codec {
+    // Files with custom separators can't carry a schema header, so there's nothing to inherit.
+    if options.schema_version.is_none() && !options.has_custom_separators() && let Some(version) = schema::read_schema_version(path)? {
+        let options = SaveOptions { schema_version: Some(version), ..options.clone() };
+        return commit_reporting(path, &options, |writer| write_records(writer, contents, &options, codec));
     }
+    commit_reporting(path, options, |writer| write_records(writer, contents, options, codec))
+}
+
+// Writes a new data file through `write`, which returns how many entries it wrote, and swaps it
+// into place, taking care of compression, backups, durability and atomicity. The caller must
+// hold the database's `FileLock`.
+fn commit<F>(path: &str, options: &SaveOptions, write: F) -> Result<(), DBError> where F: FnOnce(&mut dyn Write) -> Result<usize, DBError> {
+    commit_reporting(path, options, write)?;
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use std::collections::HashMap;
+// `commit`, reporting what it did.
+fn commit_reporting<F>(path: &str, options: &SaveOptions, write: F) -> Result<SaveReport, DBError> where F: FnOnce(&mut dyn Write) -> Result<usize, DBError> {
+    let temp_path: String = get_tmp_path(path);
+    let mut guard = TempFileGuard { path: &temp_path, renamed: false };
+    let report: SaveReport = commit_via(path, &temp_path, options, write)?;
+    guard.renamed = true;
+    Ok(report)
+}
 
-    #[test]
-    fn save_and_load_round_trip_strings() {
-        let path = "target/test_db_strings.txt";
+// Deletes a save's temp file when dropped, so a save that fails or panics part-way doesn't leave
+// it behind, unless it was renamed into place.
+struct TempFileGuard<'a> {
+    path: &'a str,
+    renamed: bool,
+}
 
-        // ensure a clean slate for this test file
-        let _ = fs::remove_file(path);
+impl Drop for TempFileGuard<'_> {
+    fn drop(&mut self) {
+        if !self.renamed {
+            let _ = fs::remove_file(self.path);
+        }
+    }
+}
 
-        let mut original: DB<String> = HashMap::new();
-        original.insert("key1".to_string(), "value1".to_string());
-        original.insert("key2".to_string(), "value2".to_string());
+// `commit`, writing through `temp_path`, which is left behind if this fails.
+fn commit_via<F>(path: &str, temp_path: &str, options: &SaveOptions, write: F) -> Result<SaveReport, DBError> where F: FnOnce(&mut dyn Write) -> Result<usize, DBError> {
+    let file_path = get_db_path(path);
 
-        save_db(path, &original).expect("saving db should succeed");
-        let loaded: DB<String> = load_db(path).expect("loading db should succeed");
+    let exists: bool = fs::exists(&file_path)?;
+    let (temp_file, entries) = write_temp_file(temp_path, options, write)?;
+    if options.fsync {
+        temp_file.sync_all()?;
+    }
+    // There's nothing to back up before the first save, and re-saving identical contents would
+    // only push a meaningful backup out of the retention window.
+    let wants_backup = exists && (options.force_backup || records_differ(&file_path, temp_path)?);
+    let now: DateTime<FixedOffset> = (options.clock)();
+    let mut backup_path: Option<PathBuf> = None;
+    if options.backup_policy.takes_backups() && wants_backup {
+        match take_backup(&file_path, &options.resolve_backup_dir(path), &now) {
+            Ok(path) => {
+                log::info!("created backup {}", path.display());
+                backup_path = Some(path);
+            }
+            Err(e) if options.backup == BackupMode::BestEffort => {
+                log::warn!("could not back up {}, saving without a backup: {}", file_path, e);
+            }
+            Err(e) => return Err(e),
+        }
+    } else if exists && !wants_backup {
+        log::debug!("contents of {} unchanged, skipping backup", file_path);
+    }
+    let backup_dir: PathBuf = options.resolve_backup_dir(path);
+    match delete_old_backups(&backup_dir, &options.backup_policy, &now) {
+        Err(e) if options.backup == BackupMode::BestEffort => {
+            log::warn!("could not prune backups in {}: {}", backup_dir.display(), e);
+        }
+        result => result?,
+    }
+    // The temp file sits next to the DB file, so this rename stays on one filesystem and
+    // readers only ever observe the old or the new file, never a partially written one.
+    fs::rename(temp_path, &file_path)?;
+    if options.fsync {
+        // Persist the rename itself by flushing the directory entry.
+        sync_dir(Path::new(get_db_dir(path)))?;
+    }
+    let bytes_written: usize = temp_file.metadata()?.len() as usize;
+    log::debug!("saved {} entries ({} bytes) to {}", entries, bytes_written, file_path);
+    // Pruning could in principle remove the backup just taken, e.g. with `KeepCount(0)`.
+    backup_path = backup_path.filter(|path| path.exists());
+    Ok(SaveReport { backup_path, bytes_written, entries })
+}
 
-        assert_eq!(original, loaded);
+// Copies the data file into `backup_dir`, returning the backup's path.
+fn take_backup(file_path: &str, backup_dir: &Path, now: &DateTime<FixedOffset>) -> Result<PathBuf, DBError> {
+    if !fs::exists(backup_dir)? {
+        fs::create_dir_all(backup_dir)?;
     }
+    let path = backup_dir.join(backup_file_name(now));
+    fs::copy(file_path, &path)?;
+    Ok(path)
+}
 
-    #[test]
-    fn save_and_load_empty_db() {
-        let path = "target/test_db_empty.txt";
+// Compares two data files record by record. Record order is ignored since it follows `HashMap`
+// iteration order, which differs between maps holding the same entries. In a pretty file a record
+// spans several lines, which are kept together so that nested values can't trade places unseen.
+fn records_differ(a: &str, b: &str) -> Result<bool, DBError> {
+    Ok(contents_differ(&read_file_bytes(Path::new(a))?, &read_file_bytes(Path::new(b))?))
+}
 
-        let _ = fs::remove_file(path);
+// `records_differ` for contents already in memory.
+fn contents_differ(a: &[u8], b: &[u8]) -> bool {
+    if a == b {
+        return false;
+    }
+    let (Ok(a), Ok(b)) = (std::str::from_utf8(a), std::str::from_utf8(b)) else {
+        // At least one side is a binary database, which has no line structure to compare.
+        return true;
+    };
+    let mut a_records: Vec<&str> = logical_lines(a, is_pretty(a.lines())).map(|(_, record)| record).collect();
+    let mut b_records: Vec<&str> = logical_lines(b, is_pretty(b.lines())).map(|(_, record)| record).collect();
+    a_records.sort_unstable();
+    b_records.sort_unstable();
+    a_records != b_records
+}
 
-        let original: DB<String> = HashMap::new();
+fn get_lock_path(path: &str) -> String {
+    format!("{}/memory.db.lock", get_db_dir(path))
+}
 
-        save_db(path, &original).expect("saving empty db should succeed");
-        let loaded: DB<String> = load_db(path).expect("loading empty db should succeed");
+// An advisory exclusive lock on `<db_dir>/memory.db.lock`, held for the duration of a save so
+// that concurrent savers, in this process or another, take turns instead of racing on the temp
+// file. It's released when the guard is dropped.
+struct FileLock {
+    file: fs::File,
+}
 
-        assert!(loaded.is_empty());
+impl FileLock {
+    fn acquire(path: &str, timeout: Duration) -> Result<FileLock, DBError> {
+        let lock_path = get_lock_path(path);
+        let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(FileLock { file }),
+                Err(fs::TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(LOCK_RETRY_INTERVAL);
+                }
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(DBError::LockTimeout(PathBuf::from(lock_path)));
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn write_temp_file<F>(temp_path: &str, options: &SaveOptions, write: F) -> Result<(fs::File, usize), DBError> where F: FnOnce(&mut dyn Write) -> Result<usize, DBError> {
+    // Records are written one small line at a time, so buffer them into fewer, larger writes.
+    let mut writer = BufWriter::new(fs::File::create(temp_path)?);
+    let entries: usize = if options.compress {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        let entries: usize = write(&mut encoder)?;
+        writer = encoder.finish()?;
+        entries
+    } else {
+        write(&mut writer)?
+    };
+    Ok((writer.into_inner().map_err(|e| e.into_error())?, entries))
+}
+
+// Returns the number of records written.
+fn write_records<T, W, C>(writer: &mut W, contents: &DB<T>, options: &SaveOptions, codec: &C) -> Result<usize, DBError> where T: Serialize, W: Write + ?Sized, C: Codec {
+    if options.canonical {
+        // `Value` objects are `BTreeMap`s, so the conversion sorts map keys at any depth.
+        let canonical: DB<serde_json::Value> = contents.iter()
+            .map(|(key, value)| Ok((key.clone(), serde_json::to_value(value)?)))
+            .collect::<Result<_, DBError>>()?;
+        let options = SaveOptions { canonical: false, sorted: true, ..options.clone() };
+        return write_records(writer, &canonical, &options, codec);
+    }
+    let mut entries: Vec<(&String, &T)> = contents.iter().collect();
+    if options.sorted {
+        entries.sort_unstable_by_key(|(key, _)| *key);
+    }
+    write_headers(writer, options)?;
+    let count: usize = entries.len();
+    // One buffer for every line, rather than a fresh allocation per record.
+    let mut line: String = String::new();
+    for (key, value) in entries {
+        line.clear();
+        if options.pretty {
+            push_record(&mut line, key, &encode_pretty_value(codec, key, value)?, options.checksums);
+        } else if options.has_custom_separators() {
+            line.push_str(&separators::format_separated(codec, key, value, options)?);
+        } else {
+            push_record(&mut line, key, &encode_value(codec, key, value)?, options.checksums);
+        }
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(count)
+}
+
+// Writes the header lines `options` calls for, which precede every record.
+fn write_headers<W>(writer: &mut W, options: &SaveOptions) -> Result<(), DBError> where W: Write + ?Sized {
+    if options.checksums {
+        writer.write_all(format!("{}{}", CHECKSUM_HEADER, options.record_separator).as_bytes())?;
+    }
+    if options.pretty {
+        writer.write_all(format!("{}{}", PRETTY_HEADER, options.record_separator).as_bytes())?;
+    }
+    if let Some(version) = options.schema_version {
+        writer.write_all(format!("{}{}{}", SCHEMA_HEADER_PREFIX, version, options.record_separator).as_bytes())?;
+    }
+    Ok(())
+}
+
+// Formats one `key=value` line, including its trailing newline.
+fn format_record<T, C>(codec: &C, key: &str, value: &T, checksum: bool) -> Result<String, DBError> where T: Serialize, C: Codec {
+    Ok(format_line(&encode_key(key), &encode_value(codec, key, value)?, checksum))
+}
+
+// Like `format_record`, appending the line to `out` instead of allocating one.
+fn push_record(out: &mut String, key: &str, value: &str, checksum: bool) {
+    push_encoded_key(out, key, |_| false);
+    out.push('=');
+    out.push_str(value);
+    if checksum {
+        let _ = write!(out, "#{:08x}", crc32fast::hash(value.as_bytes()));
+    }
+    out.push('\n');
+}
+
+// Like `format_record`, for a key and value that are already encoded.
+fn format_line(raw_key: &str, value: &str, checksum: bool) -> String {
+    if checksum {
+        return format!("{}={}#{}\n", raw_key, value, value_checksum(value));
+    }
+    format!("{}={}\n", raw_key, value)
+}
+
+// Keys are percent-encoded so they can't break the `key=value` line structure: `%`, `=` and
+// control characters (including newlines) are always escaped, and so is whitespace at either
+// end of the key, which the loader would otherwise trim away, and a leading `#`, which would
+// turn the line into a comment.
+fn encode_key(key: &str) -> String {
+    encode_key_escaping(key, |_| false)
+}
+
+// `encode_key`, also escaping every character for which `also` returns true.
+fn encode_key_escaping<F>(key: &str, also: F) -> String where F: Fn(char) -> bool {
+    let mut encoded = String::with_capacity(key.len());
+    push_encoded_key(&mut encoded, key, also);
+    encoded
+}
+
+// `encode_key_escaping`, appending to `encoded`.
+fn push_encoded_key<F>(encoded: &mut String, key: &str, also: F) where F: Fn(char) -> bool {
+    let last = key.chars().count().saturating_sub(1);
+    for (i, c) in key.chars().enumerate() {
+        let at_edge = i == 0 || i == last;
+        if c == '%' || c == '=' || c.is_control() || (at_edge && c.is_whitespace()) || (i == 0 && c == '#') || also(c) {
+            let mut buf = [0u8; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+}
+
+fn decode_key(encoded: &str) -> Result<String, DBError> {
+    let bytes = encoded.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            encoded.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| DBError::InvalidKeyEncoding(encoded.to_string()))
+}
+
+// serde_json's compact output escapes newlines inside strings, so a record always fits on one
+// line. This is checked rather than assumed, since a raw newline would silently split the record,
+// and other codecs may not be as careful.
+fn encode_value<T, C>(codec: &C, key: &str, value: &T) -> Result<String, DBError> where T: Serialize, C: Codec {
+    let encoded = codec.encode(value)?;
+    if encoded.contains(['\n', '\r']) {
+        return Err(DBError::InvalidValue {
+            key: key.to_string(),
+            reason: "serialized value contains a line break".to_string(),
+        });
+    }
+    Ok(encoded)
+}
+
+// Like `encode_value`, but lets the codec spread the value over several lines, indenting all
+// but the first so the loader reads them as continuation lines. A raw value loaded from a pretty
+// file is already indented, and happens to pass through JSON serialization verbatim, so it's
+// written as it is rather than indented again.
+fn encode_pretty_value<T, C>(codec: &C, key: &str, value: &T) -> Result<String, DBError> where T: Serialize, C: Codec {
+    let encoded = codec.encode_pretty(value)?;
+    if encoded.contains('\r') {
+        return Err(DBError::InvalidValue {
+            key: key.to_string(),
+            reason: "serialized value contains a carriage return".to_string(),
+        });
+    }
+    if encoded.lines().skip(1).all(is_continuation) {
+        return Ok(encoded);
+    }
+    Ok(encoded.replace('\n', "\n  "))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), DBError> {
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+// Directories can't be opened as files on other platforms; the rename is as durable as the OS makes it.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), DBError> {
+    Ok(())
+}
+
+fn delete_old_backups(backup_dir: &Path, policy: &BackupPolicy, now: &DateTime<FixedOffset>) -> Result<(), DBError> {
+    // Anything in the directory that isn't named like a backup (a README, `.DS_Store`, ...)
+    // is left alone and doesn't count towards the limit.
+    let (backups, unrecognized) = read_backups(backup_dir)?;
+    for name in unrecognized {
+        log::warn!("skipping {} in {}: not a backup file name", name.to_string_lossy(), backup_dir.display());
+    }
+
+    for (rank, (timestamp, name)) in backups.iter().rev().enumerate() {
+        if !policy.keeps(rank, now.signed_duration_since(timestamp)) {
+            fs::remove_file(backup_dir.join(name))?;
+            log::info!("pruned backup {}", backup_dir.join(name).display());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the timestamps of all backups under `path`, oldest first. A missing `backups/`
+/// directory yields an empty list; a file name that isn't a backup timestamp is an error.
+pub fn list_backups(path: &str) -> Result<Vec<DateTime<FixedOffset>>, DBError> {
+    list_backups_in(&get_backup_dir(path))
+}
+
+/// Like `list_backups`, for backups written to a custom `SaveOptions::backup_dir`.
+pub fn list_backups_in(backup_dir: &Path) -> Result<Vec<DateTime<FixedOffset>>, DBError> {
+    let (backups, unrecognized) = read_backups(backup_dir)?;
+    if let Some(name) = unrecognized.first() {
+        return Err(DBError::BackupParse(name.to_string_lossy().into_owned()));
+    }
+    Ok(backups.into_iter().map(|(timestamp, _)| timestamp).collect())
+}
+
+// Splits the backup directory into recognized backups, sorted oldest first, and the names of
+// any other entries.
+fn read_backups(backup_dir: &Path) -> Result<BackupListing, DBError> {
+    let mut backups: Vec<(DateTime<FixedOffset>, String)> = Vec::new();
+    let mut unrecognized: Vec<OsString> = Vec::new();
+    if !backup_dir.exists() {
+        return Ok((backups, unrecognized));
+    }
+
+    for entry in fs::read_dir(backup_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let timestamp = file_name.to_str().and_then(parse_backup_file_name);
+        match timestamp {
+            Some(timestamp) if entry.file_type()?.is_file() => {
+                backups.push((timestamp, file_name.to_string_lossy().into_owned()));
+            }
+            _ => unrecognized.push(file_name),
+        }
+    }
+    backups.sort();
+    Ok((backups, unrecognized))
+}
+
+fn backup_file_name(timestamp: &DateTime<FixedOffset>) -> String {
+    timestamp.format(BACKUP_NAME_FORMAT).to_string()
+}
+
+// Backups written before the Windows-safe naming used plain RFC 3339 names; keep reading those.
+fn parse_backup_file_name(name: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(name, BACKUP_NAME_FORMAT)
+        .or_else(|_| DateTime::parse_from_rfc3339(name))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::collections::HashMap;
+
+    #[test]
+    fn save_and_load_round_trip_strings() {
+        let path = "target/test_db_strings.txt";
+
+        // ensure a clean slate for this test file
+        let _ = fs::remove_file(path);
+
+        let mut original: DB<String> = HashMap::new();
+        original.insert("key1".to_string(), "value1".to_string());
+        original.insert("key2".to_string(), "value2".to_string());
+
+        save_db(path, &original).expect("saving db should succeed");
+        let loaded: DB<String> = load_db(path).expect("loading db should succeed");
+
+        assert_eq!(original, loaded);
+    }
+
+    #[test]
+    fn save_and_load_empty_db() {
+        let path = "target/test_db_empty.txt";
+
+        let _ = fs::remove_file(path);
+
+        let original: DB<String> = HashMap::new();
+
+        save_db(path, &original).expect("saving empty db should succeed");
+        let loaded: DB<String> = load_db(path).expect("loading empty db should succeed");
+
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn interrupted_save_keeps_previous_contents() {
+        let path = "target/test_db_interrupted";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut original: DB<String> = HashMap::new();
+        original.insert("key1".to_string(), "value1".to_string());
+        save_db(path, &original).expect("saving db should succeed");
+
+        // Simulate a crash after the temp file is written but before it is renamed into place.
+        let mut updated: DB<String> = HashMap::new();
+        updated.insert("key1".to_string(), "changed".to_string());
+        updated.insert("key2".to_string(), "value2".to_string());
+        write_temp_file(&get_tmp_path(path), &SaveOptions::default(), |writer| write_records(writer, &updated, &SaveOptions::default(), &JsonCodec))
+            .expect("writing temp file should succeed");
+
+        let loaded: DB<String> = load_db(path).expect("loading db should succeed");
+        assert_eq!(original, loaded);
+    }
+
+    #[test]
+    fn line_by_line_load_matches_whole_file_parse() {
+        let path = "target/test_db_buffered_load";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<Vec<String>> = (0..20_000).map(|i| (format!("key{}", i), vec![i.to_string(); i % 5])).collect();
+        for options in [SaveOptions::default(), SaveOptions { checksums: true, pretty: true, compress: true, ..SaveOptions::default() }] {
+            save_db_with_options(path, &db, &options).unwrap();
+            let whole: DB<Vec<String>> = parse_db(&read_db_file(path).unwrap(), false, &JsonCodec).unwrap();
+            assert_eq!(load_db::<Vec<String>>(path).unwrap(), whole);
+            assert_eq!(whole, db);
+        }
+
+        fs::write(get_db_path(path), b"a=\"ok\"\nb=\"\xff\"\n").unwrap();
+        assert!(matches!(load_db::<String>(path), Err(DBError::Io(_))));
+    }
+
+    // Accepts at most 3 bytes per `write` call, like a pipe with a tiny buffer.
+    struct ShortWriter(Vec<u8>);
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n: usize = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_writes_lose_nothing() {
+        let db: DB<String> = (0..100).map(|i| (format!("key{}", i), "x".repeat(i))).collect();
+        for options in [SaveOptions::default(), SaveOptions { checksums: true, pretty: true, ..SaveOptions::default() }] {
+            let mut writer = ShortWriter(Vec::new());
+            write_records(&mut writer, &db, &options, &JsonCodec).expect("writing should succeed");
+            assert_eq!(parse_db::<String, _>(std::str::from_utf8(&writer.0).unwrap(), false, &JsonCodec).unwrap(), db);
+        }
+    }
+
+    #[test]
+    fn large_saves_round_trip() {
+        let path = "target/test_db_large";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<String> = (0..20_000).map(|i| (format!("key {}", i), format!("value={}\n", i))).collect();
+        for options in [SaveOptions::default(), SaveOptions { checksums: true, compress: true, ..SaveOptions::default() }] {
+            save_db_with_options(path, &db, &options).expect("saving should succeed");
+            assert_eq!(load_db::<String>(path).expect("loading should succeed"), db);
+        }
+    }
+
+    #[test]
+    fn write_and_read_through_a_buffer() {
+        let db: DB<Vec<u32>> = HashMap::from([("a".to_string(), vec![1, 2]), ("b=c".to_string(), vec![])]);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        write_db(&mut buffer, &db).expect("writing should succeed");
+        let text = String::from_utf8(buffer.clone()).unwrap();
+        assert!(text.contains("a=[1,2]\n"));
+        assert!(text.contains("b%3Dc=[]\n"));
+
+        let read: DB<Vec<u32>> = read_db(buffer.as_slice()).expect("reading should succeed");
+        assert_eq!(read, db);
+        assert!(read_db::<u32, _>("broken\n".as_bytes()).is_err());
+
+        let err = write_db(&mut Vec::new(), &HashMap::from([(String::new(), 1)])).unwrap_err();
+        assert!(matches!(err, DBError::InvalidKey { .. }));
+    }
+
+    #[test]
+    fn save_without_fsync_round_trips() {
+        let path = "target/test_db_no_fsync";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut original: DB<u32> = HashMap::new();
+        original.insert("a".to_string(), 1);
+        original.insert("b".to_string(), 2);
+
+        save_db_no_fsync(path, &original).expect("saving db should succeed");
+        let loaded: DB<u32> = load_db(path).expect("loading db should succeed");
+
+        assert_eq!(original, loaded);
+    }
+
+    #[test]
+    fn keys_with_special_characters_round_trip() {
+        let path = "target/test_db_escaped_keys";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut original: DB<String> = HashMap::new();
+        original.insert("a=b".to_string(), "equals".to_string());
+        original.insert(" padded key ".to_string(), "spaces".to_string());
+        original.insert("line\nbreak\r\n".to_string(), "newline".to_string());
+        original.insert("100%".to_string(), "percent".to_string());
+        original.insert("caf\u{e9}=\u{1F600}".to_string(), "unicode".to_string());
+
+        // Line breaks in keys are rejected by default, but the format itself can escape them.
+        let options = SaveOptions { key_validator: KeyValidator::permissive(), ..SaveOptions::default() };
+        save_db_with_options(path, &original, &options).expect("saving db should succeed");
+        let loaded: DB<String> = load_db(path).expect("loading db should succeed");
+
+        assert_eq!(original, loaded);
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected_before_writing() {
+        let path = "target/test_db_invalid_keys";
+
+        let _ = fs::remove_dir_all(path);
+
+        for bad in ["", "line\nbreak", "carriage\rreturn"] {
+            let db: DB<u32> = HashMap::from([(bad.to_string(), 1)]);
+            assert!(matches!(save_db(path, &db), Err(DBError::InvalidKey { key, .. }) if key == bad));
+        }
+        assert!(!fs::exists(get_db_path(path)).unwrap());
+
+        let validator = KeyValidator { max_len: Some(3), allowed_char: Some(|c| c.is_ascii_lowercase()), ..KeyValidator::default() };
+        assert!(validator.validate("abc").is_ok());
+        assert_eq!(validator.validate("abcd").unwrap_err().to_string(), "Invalid key abcd: key is longer than 3 bytes");
+        assert!(matches!(validator.validate("aB"), Err(DBError::InvalidKey { .. })));
+        assert!(KeyValidator::permissive().validate("").is_ok());
+    }
+
+    #[test]
+    fn separators_in_keys_are_escaped_or_rejected() {
+        let path = "target/test_db_separator_keys";
+
+        let _ = fs::remove_dir_all(path);
+
+        // Escaped by default, as far as the validator lets them through.
+        let db: DB<u32> = HashMap::from([("a=b".to_string(), 1), ("line\nbreak".to_string(), 2)]);
+        let escaping = SaveOptions { key_validator: KeyValidator::permissive(), ..SaveOptions::default() };
+        save_db_with_options(path, &db, &escaping).unwrap();
+        assert_eq!(fs::read_to_string(get_db_path(path)).unwrap().lines().count(), 2);
+        assert_eq!(load_db::<u32>(path).unwrap(), db);
+
+        let validator = KeyValidator { reject_separators: true, ..KeyValidator::permissive() };
+        let rejecting = SaveOptions { key_validator: validator, ..SaveOptions::default() };
+        for key in ["a=b", "line\nbreak"] {
+            let db: DB<u32> = HashMap::from([(key.to_string(), 1)]);
+            assert!(matches!(save_db_with_options(path, &db, &rejecting), Err(DBError::InvalidKey { key: rejected, .. }) if rejected == key));
+        }
+        let mut handle: Database<u32> = Database::in_memory_with_options(DatabaseOptions { key_validator: validator, ..DatabaseOptions::default() });
+        assert!(matches!(handle.insert("a=b", 1), Err(DBError::InvalidKey { .. })));
+        assert!(handle.insert("a-b", 1).is_ok());
+    }
+
+    #[test]
+    fn struct_values_with_newlines_round_trip() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Note {
+            title: String,
+            body: String,
+        }
+
+        let path = "target/test_db_multiline_values";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut original: DB<Note> = HashMap::new();
+        original.insert("note".to_string(), Note {
+            title: "first\nsecond".to_string(),
+            body: "a=b\r\nc".to_string(),
+        });
+
+        save_db(path, &original).expect("saving db should succeed");
+        let contents = fs::read_to_string(get_db_path(path)).expect("db file should exist");
+        assert_eq!(contents.lines().count(), 1);
+
+        let loaded: DB<Note> = load_db(path).expect("loading db should succeed");
+        assert_eq!(original, loaded);
+    }
+
+    #[test]
+    fn malformed_lines_fail_strict_load_but_not_lenient_load() {
+        let path = "target/test_db_malformed";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        fs::write(get_db_path(path), "a=1\n\nthis is garbage\nb=2\n").unwrap();
+
+        let err = load_db::<u32>(path).expect_err("strict load should reject the garbage line");
+        assert_eq!(err.to_string(), "Malformed line 3: this is garbage");
+
+        let loaded: DB<u32> = load_db_lenient(path).expect("lenient load should succeed");
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded["a"], 1);
+        assert_eq!(loaded["b"], 2);
+    }
+
+    #[test]
+    fn read_errors_are_not_treated_as_empty() {
+        let path = "target/test_db_unreadable";
+
+        let _ = fs::remove_dir_all(path);
+        // A directory where the data file should be can't be read as a file.
+        fs::create_dir_all(get_db_path(path)).unwrap();
+
+        assert!(load_db::<u32>(path).is_err());
+        assert!(load_db::<u32>("target/test_db_never_created").unwrap().is_empty());
+    }
+
+    #[test]
+    fn keep_count_limits_retained_backups() {
+        let path = "target/test_db_max_backups";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = SaveOptions { backup_policy: BackupPolicy::KeepCount(2), ..SaveOptions::default() };
+        let mut db: DB<u32> = HashMap::new();
+        for i in 0..5 {
+            db.insert("counter".to_string(), i);
+            save_db_with_options(path, &db, &options).expect("saving db should succeed");
+        }
+
+        let backups = fs::read_dir(Path::new(path).join("backups")).unwrap().count();
+        assert_eq!(backups, 2);
+
+        save_db_with_options(path, &db, &SaveOptions { backup_policy: BackupPolicy::KeepCount(0), ..options }).unwrap();
+        let backups = fs::read_dir(Path::new(path).join("backups")).unwrap().count();
+        assert_eq!(backups, 0);
+    }
+
+    #[test]
+    fn checksums_detect_a_corrupted_value() {
+        let path = "target/test_db_checksums";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<String> = HashMap::new();
+        db.insert("good".to_string(), "intact".to_string());
+        db.insert("bad".to_string(), "flipped".to_string());
+        db.insert("hash#tag".to_string(), "a # inside".to_string());
+        save_db_with_options(path, &db, &SaveOptions { checksums: true, ..SaveOptions::default() }).unwrap();
+        assert_eq!(load_db::<String>(path).unwrap(), db);
+
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        fs::write(get_db_path(path), contents.replace("flipped", "flopped")).unwrap();
+        match load_db::<String>(path) {
+            Err(DBError::ChecksumMismatch { key }) => assert_eq!(key, "bad"),
+            other => panic!("expected a checksum mismatch, got {:?}", other),
+        }
+        assert_eq!(get_one::<String>(path, "good").unwrap_err().to_string(), "Checksum mismatch for key bad");
+
+        // A line cut short loses its checksum entirely.
+        fs::write(get_db_path(path), format!("{}\ngood=\"intact\"\n", CHECKSUM_HEADER)).unwrap();
+        assert!(matches!(load_db::<String>(path), Err(DBError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn load_db_or_recover_falls_back_to_the_newest_good_backup() {
+        let path = "target/test_db_recover";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        save_db(path, &db).unwrap();
+        assert_eq!(load_db_or_recover::<u32>(path).unwrap(), (db.clone(), None));
+
+        db.insert("a".to_string(), 2);
+        save_db(path, &db).unwrap();
+        // The newest backup is corrupt too, so recovery has to skip it.
+        db.insert("a".to_string(), 3);
+        save_db(path, &db).unwrap();
+        let backups = read_backups(&get_backup_dir(path)).unwrap().0;
+        fs::write(get_backup_dir(path).join(&backups[1].1), "a=garbage\n").unwrap();
+        fs::write(get_db_path(path), "not a record\n").unwrap();
+
+        let (recovered, from) = load_db_or_recover::<u32>(path).expect("recovery should succeed");
+        assert_eq!(recovered["a"], 1);
+        assert_eq!(from, Some(backups[0].0));
+
+        for (_, name) in &backups {
+            fs::write(get_backup_dir(path).join(name), "a=garbage\n").unwrap();
+        }
+        assert!(matches!(load_db_or_recover::<u32>(path), Err(DBError::MalformedLine { .. })));
+    }
+
+    #[test]
+    fn get_or_default_saves_the_default_once() {
+        let path = "target/test_db_get_or_default";
+
+        let _ = fs::remove_dir_all(path);
+
+        assert_eq!(get_or_default::<Vec<u32>>(path, "list").unwrap(), Vec::<u32>::new());
+        assert_eq!(get_one::<Vec<u32>>(path, "list").unwrap(), Some(vec![]));
+
+        let mut db: DB<Vec<u32>> = load_db(path).unwrap();
+        db.insert("list".to_string(), vec![1, 2]);
+        save_db(path, &db).unwrap();
+        assert_eq!(get_or_default::<Vec<u32>>(path, "list").unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn compare_and_swap_checks_the_current_value() {
+        let path = "target/test_db_compare_and_swap";
+
+        let _ = fs::remove_dir_all(path);
+
+        assert!(compare_and_swap(path, "a", None, 1).unwrap());
+        assert!(!compare_and_swap(path, "a", None, 2).unwrap());
+        assert!(!compare_and_swap(path, "a", Some(&5), 2).unwrap());
+        assert_eq!(get_one::<u32>(path, "a").unwrap(), Some(1));
+        assert!(compare_and_swap(path, "a", Some(&1), 2).unwrap());
+        assert_eq!(get_one::<u32>(path, "a").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn concurrent_compare_and_swap_increments_are_not_lost() {
+        let path = "target/test_db_compare_and_swap_concurrent";
+
+        let _ = fs::remove_dir_all(path);
+        compare_and_swap(path, "n", None, 0u32).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|_| thread::spawn(move || {
+            for _ in 0..5 {
+                loop {
+                    let current: u32 = get_one(path, "n").unwrap().unwrap();
+                    if compare_and_swap(path, "n", Some(&current), current + 1).unwrap() {
+                        break;
+                    }
+                }
+            }
+        })).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(get_one::<u32>(path, "n").unwrap(), Some(20));
+    }
+
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Server {
+        host: String,
+        ports: Vec<u16>,
+        tags: HashMap<String, String>,
+    }
+
+    #[test]
+    fn pretty_files_round_trip_nested_values() {
+        let path = "target/test_db_pretty";
+
+        let _ = fs::remove_dir_all(path);
+
+        let server = Server {
+            host: "db.internal".to_string(),
+            ports: vec![5432, 5433],
+            tags: HashMap::from([("role".to_string(), "primary # of two".to_string())]),
+        };
+        let mut db: DB<Server> = HashMap::new();
+        db.insert("primary".to_string(), server.clone());
+        db.insert("  spaced".to_string(), Server { ports: Vec::new(), ..server.clone() });
+
+        for checksums in [false, true] {
+            let options = SaveOptions { pretty: true, checksums, sorted: true, key_validator: KeyValidator::permissive(), ..SaveOptions::default() };
+            save_db_with_options(path, &db, &options).unwrap();
+            let contents = fs::read_to_string(get_db_path(path)).unwrap();
+            assert!(contents.lines().count() > 10);
+            assert!(contents.contains("primary={\n    \"host\": \"db.internal\",\n"));
+            assert_eq!(load_db::<Server>(path).unwrap(), db);
+            assert_eq!(load_db_stream::<Server>(path).collect::<Result<DB<Server>, _>>().unwrap(), db);
+            assert_eq!(get_one::<Server>(path, "primary").unwrap(), Some(server.clone()));
+        }
+
+        // Rewrites on the caller's behalf stay pretty, and appended lines mix in fine.
+        compact(path).unwrap();
+        let compacted = fs::read_to_string(get_db_path(path)).unwrap();
+        assert!(compacted.starts_with("#memory_db:crc32\n#memory_db:pretty\n"));
+        assert!(compacted.lines().any(|line| line == "    \"host\": \"db.internal\","));
+        assert!(!compacted.contains("      \"host\""));
+        append_entry(path, "extra", &Server { tags: HashMap::new(), ..server }).unwrap();
+        assert_eq!(load_db::<Server>(path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn fallback_recovers_old_values_and_reports_the_rest() {
+        let path = "target/test_db_fallback";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        // Counts used to be stored as strings, and one line is beyond saving.
+        fs::write(get_db_path(path), "a=1\nb=\"2\"\nc=\"lots\"\nd=4\n").unwrap();
+
+        assert!(load_db::<u64>(path).is_err());
+        let (db, skipped) = load_db_with_fallback(path, |_, raw, _| serde_json::from_str::<String>(raw).ok()?.parse::<u64>().ok()).unwrap();
+        assert_eq!(db, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2), ("d".to_string(), 4)]));
+        assert_eq!(skipped.len(), 1);
+        assert_eq!((skipped[0].line, skipped[0].key.as_str(), skipped[0].value.as_str()), (3, "c", "\"lots\""));
+        assert!(skipped[0].error.contains("expected u64"));
+    }
+
+    #[test]
+    fn report_keeps_good_lines_and_lists_bad_ones() {
+        let path = "target/test_db_report";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(load_db_report::<u32>(path).unwrap().0.is_empty());
+        fs::create_dir_all(path).unwrap();
+        fs::write(get_db_path(path), "a=1\nnot a record\nb=2\n").unwrap();
+
+        let (db, errors) = load_db_report::<u32>(path).unwrap();
+        assert_eq!(db, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]));
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line_number, errors[0].content.as_str()), (2, "not a record"));
+        assert!(matches!(errors[0].error, DBError::MalformedLine { line: 2, .. }));
+
+        fs::write(get_db_path(path), "a=1\nb=\"two\"\n").unwrap();
+        let (db, errors) = load_db_report::<u32>(path).unwrap();
+        assert_eq!(db.len(), 1);
+        assert!(matches!(&errors[..], [LineError { line_number: 2, error: DBError::Serde(_), .. }]));
+
+        fs::write(get_db_path(path), [0xff, 0xfe]).unwrap();
+        assert!(matches!(load_db_report::<u32>(path), Err(DBError::Io(_))));
+    }
+
+    #[test]
+    fn pages_cover_every_entry_once() {
+        let path = "target/test_db_page";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(page::<u32>(path, 0, 10).unwrap().is_empty());
+
+        let db: DB<u32> = (0..25).map(|i| (format!("key{:02}", i), i)).collect();
+        save_db(path, &db).unwrap();
+
+        let pages: Vec<Vec<(String, u32)>> = (0..3).map(|n| page(path, n * 10, 10).unwrap()).collect();
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<usize>>(), vec![10, 10, 5]);
+        assert_eq!(pages[1][0], ("key10".to_string(), 10));
+        let seen: Vec<(String, u32)> = pages.into_iter().flatten().collect();
+        assert!(seen.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(seen.into_iter().collect::<DB<u32>>(), db);
+        assert!(page::<u32>(path, 25, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn patch_updates_only_the_named_fields() {
+        let path = "target/test_db_patch";
+
+        let _ = fs::remove_dir_all(path);
+
+        let user = serde_json::json!({"name": "ada", "email": "ada@example.com", "prefs": {"theme": "dark", "lang": "en"}});
+        let mut db: DB<serde_json::Value> = HashMap::new();
+        db.insert("user".to_string(), user);
+        db.insert("count".to_string(), serde_json::json!(3));
+        save_db(path, &db).unwrap();
+
+        patch(path, "user", serde_json::json!({"email": "ada@newmail.org", "prefs": {"lang": null, "font": "mono"}})).unwrap();
+        let patched: serde_json::Value = get_one(path, "user").unwrap().unwrap();
+        assert_eq!(patched, serde_json::json!({"name": "ada", "email": "ada@newmail.org", "prefs": {"theme": "dark", "font": "mono"}}));
+
+        // A value that isn't an object is replaced by the patch, per the RFC.
+        patch(path, "count", serde_json::json!({"total": 3, "gone": null})).unwrap();
+        assert_eq!(get_one::<serde_json::Value>(path, "count").unwrap(), Some(serde_json::json!({"total": 3})));
+
+        assert!(matches!(patch(path, "missing", serde_json::json!({})), Err(DBError::NotFound(_))));
+        assert_eq!(load_db::<serde_json::Value>(path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn clear_db_keeps_a_backup_of_the_cleared_data() {
+        let path = "target/test_db_clear";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        db.insert("b".to_string(), 2);
+        save_db(path, &db).unwrap();
+
+        clear_db(path).expect("clearing should succeed");
+        assert!(load_db::<u32>(path).unwrap().is_empty());
+        assert_eq!(restore_latest_backup::<u32>(path).unwrap(), db);
+        // Clearing a database that was never saved just creates an empty one.
+        let _ = fs::remove_dir_all("target/test_db_clear_new");
+        clear_db("target/test_db_clear_new").unwrap();
+        assert!(load_db::<u32>("target/test_db_clear_new").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rename_key_moves_values_without_overwriting() {
+        let path = "target/test_db_rename_key";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        db.insert("b".to_string(), 2);
+        save_db(path, &db).unwrap();
+
+        assert!(rename_key(path, "a", "c").unwrap());
+        let loaded: DB<u32> = load_db(path).unwrap();
+        assert_eq!(loaded.get("a"), None);
+        assert_eq!(loaded["c"], 1);
+
+        assert!(matches!(rename_key(path, "c", "b"), Err(DBError::KeyExists(key)) if key == "b"));
+        assert_eq!(load_db::<u32>(path).unwrap(), loaded);
+
+        assert!(!rename_key(path, "missing", "d").unwrap());
+        assert!(rename_key(path, "b", "b").unwrap());
+        assert_eq!(load_db::<u32>(path).unwrap(), loaded);
+    }
+
+    #[test]
+    fn transform_keys_rewrites_every_key() {
+        let path = "target/test_db_transform_keys";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        save_db(path, &db).unwrap();
+
+        transform_keys(path, |key| format!("v2:{}", key)).unwrap();
+        let loaded: DB<u32> = load_db(path).unwrap();
+        assert_eq!(loaded, HashMap::from([("v2:a".to_string(), 1), ("v2:b".to_string(), 2)]));
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+        assert_eq!(restore_latest_backup::<u32>(path).unwrap(), db);
+    }
+
+    #[test]
+    fn transform_keys_resolves_collisions_as_asked() {
+        let path = "target/test_db_transform_keys_collisions";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = HashMap::from([("A".to_string(), 1), ("a".to_string(), 2), ("b".to_string(), 3)]);
+        save_db(path, &db).unwrap();
+
+        let err = transform_keys(path, |key| key.to_lowercase()).unwrap_err();
+        assert!(matches!(err, DBError::KeyExists(key) if key == "a"));
+        assert_eq!(load_db::<u32>(path).unwrap(), db);
+        assert!(matches!(transform_keys(path, |_| String::new()), Err(DBError::InvalidKey { .. })));
+
+        transform_keys_with(path, |key| key.to_lowercase(), KeyCollision::LastWins).unwrap();
+        assert_eq!(load_db::<u32>(path).unwrap(), HashMap::from([("a".to_string(), 2), ("b".to_string(), 3)]));
+    }
+
+    #[test]
+    fn save_all_writes_entries_in_one_pass() {
+        let path = "target/test_db_save_all";
+
+        let _ = fs::remove_dir_all(path);
+
+        let entries = (0..3000).map(|i| (format!("key{}", i), i)).chain([("key7".to_string(), 70)]);
+        save_all(path, entries).expect("saving should succeed");
+
+        let loaded: DB<u32> = load_db(path).unwrap();
+        assert_eq!(loaded.len(), 3000);
+        assert_eq!(loaded["key2999"], 2999);
+        assert_eq!(loaded["key7"], 70);
+    }
+
+    #[test]
+    fn save_all_keeps_the_existing_format() {
+        let path = "target/test_db_save_all_format";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = SaveOptions { compress: true, checksums: true, pretty: true, schema_version: Some(2), ..SaveOptions::default() };
+        save_db_with_options(path, &HashMap::from([("old".to_string(), vec![0])]), &options).unwrap();
+        save_all(path, (0..3).map(|i| (format!("key{}", i), vec![i, i + 1]))).unwrap();
+
+        let preserved: SaveOptions = options_preserving_format(path).unwrap();
+        assert!(preserved.compress && preserved.checksums && preserved.pretty);
+        assert_eq!(preserved.schema_version, Some(2));
+        let loaded: DB<Vec<u32>> = load_db(path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded["key2"], vec![2, 3]);
+        assert!(matches!(save_all(path, [("".to_string(), vec![0])]), Err(DBError::InvalidKey { .. })));
+    }
+
+    #[test]
+    fn query_returns_matching_values() {
+        let path = "target/test_db_query";
+
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Person {
+            age: u32,
+        }
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<Person> = HashMap::new();
+        db.insert("ann".to_string(), Person { age: 25 });
+        db.insert("bob".to_string(), Person { age: 31 });
+        db.insert("cat".to_string(), Person { age: 47 });
+        save_db(path, &db).unwrap();
+
+        let older: DB<Person> = query(path, |person: &Person| person.age > 30).unwrap();
+        let mut keys: Vec<&String> = older.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["bob", "cat"]);
+        assert!(query(path, |person: &Person| person.age > 50).unwrap().is_empty());
+    }
+
+    #[test]
+    fn contains_key_matches_whole_decoded_keys() {
+        let path = "target/test_db_contains_key";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(!contains_key(path, "user").unwrap());
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("user:alice".to_string(), 1);
+        db.insert("a=b".to_string(), 2);
+        db.insert(" padded ".to_string(), 3);
+        save_db(path, &db).unwrap();
+
+        assert!(contains_key(path, "user:alice").unwrap());
+        assert!(!contains_key(path, "user").unwrap());
+        assert!(!contains_key(path, "user:alice2").unwrap());
+        assert!(contains_key(path, "a=b").unwrap());
+        assert!(!contains_key(path, "a%3Db").unwrap());
+        assert!(contains_key(path, " padded ").unwrap());
+        assert!(!contains_key(path, "padded").unwrap());
+    }
+
+    #[test]
+    fn count_entries_matches_load_db() {
+        let path = "target/test_db_count_entries";
+
+        let _ = fs::remove_dir_all(path);
+        assert_eq!(count_entries(path).unwrap(), 0);
+
+        let db: DB<Vec<u32>> = (0..50).map(|i| (format!("key{}", i), vec![i; 3])).collect();
+        save_db(path, &db).unwrap();
+        append_entry(path, "key0", &vec![1u32]).unwrap();
+        let mut contents = fs::read_to_string(get_db_path(path)).unwrap();
+        contents.push_str("# a comment\n\n");
+        fs::write(get_db_path(path), contents).unwrap();
+
+        assert_eq!(count_entries(path).unwrap(), load_db::<Vec<u32>>(path).unwrap().len());
+        assert_eq!(count_entries(path).unwrap(), 50);
+    }
+
+    struct CapturingLogger {
+        lines: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.lines.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger { lines: std::sync::Mutex::new(Vec::new()) };
+
+    #[test]
+    fn saves_and_backups_are_logged() {
+        let path = "target/test_db_logging";
+
+        let _ = fs::remove_dir_all(path);
+        // Other tests log too, so only look for lines mentioning this test's path.
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        db.insert("b".to_string(), 2);
+        save_db(path, &db).unwrap();
+        save_db(path, &db).unwrap();
+        db.insert("b".to_string(), 3);
+        save_db(path, &db).unwrap();
+        load_db::<u32>(path).unwrap();
+
+        let lines = LOGGER.lines.lock().unwrap();
+        let mine: Vec<&String> = lines.iter().filter(|line| line.contains(path)).collect();
+        assert!(mine.iter().any(|line| line.as_str() == "DEBUG saved 2 entries (8 bytes) to target/test_db_logging/memory.db"));
+        assert!(mine.iter().any(|line| line.starts_with("INFO created backup target/test_db_logging/backups/")));
+        assert!(mine.iter().any(|line| line.as_str() == "DEBUG contents of target/test_db_logging/memory.db unchanged, skipping backup"));
+        assert!(mine.iter().any(|line| line.as_str() == "DEBUG loaded 2 entries (8 bytes) from target/test_db_logging/memory.db"));
+    }
+
+    // Plants empty backups `days_ago` days old, to stand in for backups taken on earlier saves.
+    fn plant_backups(path: &str, days_ago: &[i64]) {
+        fs::create_dir_all(get_backup_dir(path)).unwrap();
+        let now = chrono::Local::now().fixed_offset();
+        for days in days_ago {
+            let name = backup_file_name(&(now - TimeDelta::days(*days)));
+            fs::write(get_backup_dir(path).join(name), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn keep_within_prunes_backups_by_age() {
+        let path = "target/test_db_keep_within";
+
+        let _ = fs::remove_dir_all(path);
+        plant_backups(path, &[30, 10, 6, 1]);
+
+        let options = SaveOptions { backup_policy: BackupPolicy::KeepWithin(TimeDelta::days(7)), ..SaveOptions::default() };
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("counter".to_string(), 1);
+        save_db_with_options(path, &db, &options).unwrap();
+
+        let now = chrono::Local::now().fixed_offset();
+        let ages: Vec<i64> = list_backups(path).unwrap().iter().map(|t| (now - *t).num_days()).collect();
+        assert_eq!(ages, vec![6, 1]);
+    }
+
+    #[test]
+    fn keep_count_or_within_keeps_either() {
+        let path = "target/test_db_keep_count_or_within";
+
+        let _ = fs::remove_dir_all(path);
+        plant_backups(path, &[30, 20, 10, 2, 1]);
+
+        // The 10-day-old backup survives on count alone, the recent ones on age.
+        let policy = BackupPolicy::KeepCountOrWithin(3, TimeDelta::days(5));
+        let options = SaveOptions { backup_policy: policy, ..SaveOptions::default() };
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("counter".to_string(), 1);
+        save_db_with_options(path, &db, &options).unwrap();
+        let now = chrono::Local::now().fixed_offset();
+        let ages: Vec<i64> = list_backups(path).unwrap().iter().map(|t| (now - *t).num_days()).collect();
+        assert_eq!(ages, vec![10, 2, 1]);
+
+        // And here the age window keeps more than the count would.
+        let policy = BackupPolicy::KeepCountOrWithin(1, TimeDelta::hours(36));
+        db.insert("counter".to_string(), 2);
+        save_db_with_options(path, &db, &SaveOptions { backup_policy: policy, ..options }).unwrap();
+        let ages: Vec<i64> = list_backups(path).unwrap().iter().map(|t| (now - *t).num_days()).collect();
+        assert_eq!(ages, vec![1, 0]);
+    }
+
+    // A clock that starts at midnight UTC on 2026-01-01 and advances an hour per reading.
+    static FAKE_HOURS: AtomicU64 = AtomicU64::new(0);
+
+    fn fake_clock() -> DateTime<FixedOffset> {
+        let hours: u64 = FAKE_HOURS.fetch_add(1, Ordering::Relaxed);
+        DateTime::parse_from_rfc3339("2026-01-01T00:00:00+00:00").unwrap() + TimeDelta::hours(hours as i64)
+    }
+
+    #[test]
+    fn backups_follow_the_configured_clock() {
+        let path = "target/test_db_fake_clock";
+
+        let _ = fs::remove_dir_all(path);
+
+        // Each save reads the clock once; the first has nothing to back up.
+        let policy = BackupPolicy::KeepWithin(TimeDelta::hours(2));
+        let options = SaveOptions { backup_policy: policy, force_backup: true, clock: fake_clock, ..SaveOptions::default() };
+        for i in 0..5 {
+            save_db_with_options(path, &HashMap::from([("counter".to_string(), i)]), &options).unwrap();
+        }
+
+        // At 04:00, the 02:00 backup is two hours old and pruned.
+        let mut names: Vec<String> = fs::read_dir(get_backup_dir(path)).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["20260101T030000.000000000+0000", "20260101T040000.000000000+0000"]);
+        assert_eq!(restore_from_backup::<u32>(path, &names[0]).unwrap()["counter"], 2);
+    }
+
+    // The temp files left in the database directory.
+    fn temp_files(path: &str) -> Vec<String> {
+        fs::read_dir(get_db_dir(path)).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_saves_leave_one_complete_file() {
+        let path = "target/test_db_concurrent_saves";
+
+        let _ = fs::remove_dir_all(path);
+
+        let dbs: Vec<DB<usize>> = (0..2).map(|writer| (0..2_000).map(|i| (format!("key{}", i), writer)).collect()).collect();
+        thread::scope(|scope| {
+            for db in &dbs {
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        save_db(path, db).expect("saving should succeed");
+                    }
+                });
+            }
+        });
+
+        let loaded: DB<usize> = load_db(path).unwrap();
+        assert!(dbs.contains(&loaded));
+        assert!(temp_files(path).is_empty());
+    }
+
+    // Fails to serialize, after the temp file has been created.
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S>(&self, _: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+            Err(serde::ser::Error::custom("can't serialize this"))
+        }
+    }
+
+    #[test]
+    fn failed_saves_remove_their_temp_file() {
+        let path = "target/test_db_failed_save_temp";
+
+        let _ = fs::remove_dir_all(path);
+        save_db(path, &HashMap::from([("a".to_string(), 1)])).unwrap();
+
+        let err = save_db(path, &HashMap::from([("b".to_string(), Unserializable)])).unwrap_err();
+        assert!(matches!(err, DBError::Serde(_)));
+        assert!(temp_files(path).is_empty());
+        assert_eq!(load_db::<u32>(path).unwrap()["a"], 1);
+    }
+
+    #[test]
+    fn stale_temp_files_are_overwritten() {
+        let path = "target/test_db_stale_temp";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1)]);
+
+        // A temp file whose name gets reused starts from empty rather than being appended to.
+        let temp_path: String = get_tmp_path(path);
+        fs::write(&temp_path, "junk=1\nbroken\n").unwrap();
+        write_temp_file(&temp_path, &SaveOptions::default(), |writer| write_records(writer, &db, &SaveOptions::default(), &JsonCodec)).unwrap();
+        assert_eq!(fs::read_to_string(&temp_path).unwrap(), "a=1\n");
+
+        // Nor does one left by an older version get in the way.
+        fs::write(format!("{}/memory.db.tmp", path), "junk=1\nbroken\n").unwrap();
+        save_db(path, &db).unwrap();
+        assert_eq!(fs::read_to_string(get_db_path(path)).unwrap(), "a=1\n");
+    }
+
+    #[test]
+    fn missing_backup_directory_is_nothing_to_prune() {
+        let path = "target/test_db_missing_backups";
+
+        let _ = fs::remove_dir_all(path);
+        let now = chrono::Local::now().fixed_offset();
+        delete_old_backups(&get_backup_dir(path), &BackupPolicy::KeepCount(1), &now).expect("pruning nothing should succeed");
+
+        let options = SaveOptions { backup_policy: BackupPolicy::KeepCount(1), ..SaveOptions::default() };
+        save_db_with_options(path, &HashMap::from([("a".to_string(), 1)]), &options).expect("first save should succeed");
+        save_db_with_options(path, &HashMap::from([("a".to_string(), 2)]), &options).unwrap();
+        fs::remove_dir_all(get_backup_dir(path)).unwrap();
+        save_db_with_options(path, &HashMap::from([("a".to_string(), 3)]), &options).expect("save without backups/ should succeed");
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn save_reports_its_backup() {
+        let path = "target/test_db_save_report";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let first: SaveReport = save_db_reporting(path, &db).unwrap();
+        assert_eq!(first, SaveReport { backup_path: None, bytes_written: 8, entries: 2 });
+
+        db.insert("c".to_string(), 3);
+        let second: SaveReport = save_db_reporting(path, &db).unwrap();
+        assert_eq!(second.entries, 3);
+        assert_eq!(second.bytes_written as u64, fs::metadata(get_db_path(path)).unwrap().len());
+        let backup_path: PathBuf = second.backup_path.expect("a backup should be reported");
+        assert!(backup_path.starts_with(get_backup_dir(path)));
+        assert_eq!(fs::read_to_string(&backup_path).unwrap().len(), 8);
+
+        // Unchanged contents take no backup.
+        assert_eq!(save_db_reporting(path, &db).unwrap().backup_path, None);
+    }
+
+    #[test]
+    fn first_save_takes_no_backup() {
+        let path = "target/test_db_first_save";
+
+        let _ = fs::remove_dir_all(path);
+
+        let forced = SaveOptions { force_backup: true, ..SaveOptions::default() };
+        save_db_with_options(path, &HashMap::from([("a".to_string(), 1)]), &forced).unwrap();
+        assert!(!fs::exists(get_backup_dir(path)).unwrap());
+        assert!(temp_files(path).is_empty());
+
+        save_db(path, &HashMap::from([("a".to_string(), 2)])).unwrap();
+        let backups = read_backups(&get_backup_dir(path)).unwrap().0;
+        assert_eq!(backups.len(), 1);
+        assert_eq!(restore_from_backup::<u32>(path, &backups[0].1).unwrap()["a"], 1);
+    }
+
+    #[test]
+    fn restore_backups_after_a_bad_write() {
+        let path = "target/test_db_restore";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(restore_latest_backup::<String>(path).is_err());
+
+        let mut first: DB<String> = HashMap::new();
+        first.insert("key".to_string(), "first".to_string());
+        save_db(path, &first).expect("first save should succeed");
+
+        let mut second: DB<String> = HashMap::new();
+        second.insert("key".to_string(), "second".to_string());
+        save_db(path, &second).expect("second save should succeed");
+
+        // The second save backed up the contents written by the first.
+        let latest: DB<String> = restore_latest_backup(path).expect("restoring latest should succeed");
+        assert_eq!(latest, first);
+
+        let names: Vec<String> = fs::read_dir(get_backup_dir(path)).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        // The first save had no previous file to back up.
+        assert_eq!(names.len(), 1);
+        let by_name: DB<String> = restore_from_backup(path, &names[0]).expect("restoring by name should succeed");
+        assert_eq!(by_name, first);
+
+        let err = restore_from_backup::<String>(path, "missing").expect_err("missing backup should fail");
+        assert!(matches!(err, DBError::NotFound(_)));
+    }
+
+    #[test]
+    fn list_backups_returns_sorted_timestamps() {
+        let path = "target/test_db_list_backups";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(list_backups(path).expect("listing without backups should succeed").is_empty());
+
+        let mut db: DB<u32> = HashMap::new();
+        for i in 0..3 {
+            db.insert("counter".to_string(), i);
+            save_db(path, &db).expect("saving db should succeed");
+        }
+
+        let backups = list_backups(path).expect("listing backups should succeed");
+        assert_eq!(backups.len(), 2);
+        assert!(backups.windows(2).all(|pair| pair[0] < pair[1]));
+
+        fs::write(get_backup_dir(path).join("not-a-backup"), "").unwrap();
+        assert!(list_backups(path).is_err());
+    }
+
+    #[test]
+    fn backup_file_names_are_windows_safe() {
+        let path = "target/test_db_backup_names";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::new();
+        save_db(path, &db).expect("saving db should succeed");
+        db.insert("a".to_string(), 1);
+        save_db(path, &db).expect("saving db should succeed");
+
+        let names: Vec<String> = fs::read_dir(get_backup_dir(path)).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(!names[0].contains(':'), "backup name {} contains a colon", names[0]);
+        assert!(parse_backup_file_name(&names[0]).is_some());
+    }
+
+    #[test]
+    fn legacy_rfc3339_backups_are_still_recognized() {
+        let path = "target/test_db_legacy_backups";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(get_backup_dir(path)).unwrap();
+        fs::write(get_backup_dir(path).join("2020-01-02T03:04:05+00:00"), "k=1\n").unwrap();
+
+        let mut db: DB<u32> = HashMap::new();
+        save_db(path, &db).expect("saving db should succeed");
+        db.insert("k".to_string(), 2);
+        save_db(path, &db).expect("saving db should succeed");
+
+        let backups = list_backups(path).expect("listing backups should succeed");
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].to_rfc3339(), "2020-01-02T03:04:05+00:00");
+    }
+
+    #[test]
+    fn stray_files_in_backups_are_ignored() {
+        let path = "target/test_db_stray_backup_files";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(get_backup_dir(path)).unwrap();
+        let notes = get_backup_dir(path).join("notes.txt");
+        fs::write(&notes, "keep me").unwrap();
+
+        let options = SaveOptions { backup_policy: BackupPolicy::KeepCount(1), ..SaveOptions::default() };
+        let mut db: DB<u32> = HashMap::new();
+        for i in 0..3 {
+            db.insert("counter".to_string(), i);
+            save_db_with_options(path, &db, &options).expect("saving db should succeed");
+        }
+
+        assert_eq!(fs::read_to_string(&notes).unwrap(), "keep me");
+        assert_eq!(fs::read_dir(get_backup_dir(path)).unwrap().count(), 2);
+        let latest: DB<u32> = restore_latest_backup(path).expect("restoring should skip notes.txt");
+        assert_eq!(latest["counter"], 1);
+    }
+
+    #[test]
+    fn get_one_reads_a_single_key() {
+        let path = "target/test_db_get_one";
+
+        let _ = fs::remove_dir_all(path);
+        assert_eq!(get_one::<u32>(path, "key1").unwrap(), None);
+
+        let mut db: DB<u32> = HashMap::new();
+        for i in 0..1000 {
+            db.insert(format!("key{}", i), i);
+        }
+        db.insert("a=b".to_string(), 7);
+        save_db(path, &db).expect("saving db should succeed");
+
+        assert_eq!(get_one::<u32>(path, "key1").unwrap(), Some(1));
+        assert_eq!(get_one::<u32>(path, "key999").unwrap(), Some(999));
+        assert_eq!(get_one::<u32>(path, "a=b").unwrap(), Some(7));
+        assert_eq!(get_one::<u32>(path, "a").unwrap(), None);
+        assert_eq!(get_one::<u32>(path, "key1000").unwrap(), None);
+    }
+
+    #[test]
+    fn backups_land_in_the_configured_directory() {
+        let root = "target/test_db_backup_dir";
+        let path = "target/test_db_backup_dir/sub";
+        let custom = Path::new(root).join("elsewhere");
+
+        let _ = fs::remove_dir_all(root);
+
+        let mut db: DB<u32> = HashMap::new();
+        save_db(path, &db).expect("saving db should succeed");
+        db.insert("first".to_string(), 0);
+        save_db(path, &db).expect("saving db should succeed");
+        assert_eq!(list_backups_in(&Path::new(path).join("backups")).unwrap().len(), 1);
+
+        let options = SaveOptions { backup_dir: Some(custom.clone()), backup_policy: BackupPolicy::KeepCount(2), ..SaveOptions::default() };
+        for i in 0..3 {
+            db.insert("counter".to_string(), i);
+            save_db_with_options(path, &db, &options).expect("saving db should succeed");
+        }
+        assert_eq!(list_backups_in(&custom).unwrap().len(), 2);
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn canonical_saves_ignore_insertion_order() {
+        let first_path = "target/test_db_canonical_first";
+        let second_path = "target/test_db_canonical_second";
+
+        let _ = fs::remove_dir_all(first_path);
+        let _ = fs::remove_dir_all(second_path);
+
+        type Nested = HashMap<String, HashMap<String, usize>>;
+        let names: Vec<&str> = vec!["zeta", "alpha", "mu", "beta", "omega", "delta", "kappa", "gamma"];
+        let reversed: Vec<&str> = names.iter().rev().copied().collect();
+        // Each map is built in the given order, at every level.
+        let build = |order: &[&str]| -> DB<Nested> {
+            order.iter().map(|key| {
+                let value: Nested = order.iter().map(|name| {
+                    (name.to_string(), order.iter().map(|inner| (inner.to_string(), inner.len())).collect())
+                }).collect();
+                (key.to_string(), value)
+            }).collect()
+        };
+        let first: DB<Nested> = build(&names);
+        let second: DB<Nested> = build(&reversed);
+
+        let options = SaveOptions { canonical: true, ..SaveOptions::default() };
+        save_db_with_options(first_path, &first, &options).unwrap();
+        save_db_with_options(second_path, &second, &options).unwrap();
+        let bytes: Vec<u8> = fs::read(get_db_path(first_path)).unwrap();
+        assert_eq!(bytes, fs::read(get_db_path(second_path)).unwrap());
+
+        let text: String = String::from_utf8(bytes).unwrap();
+        let mut sorted_names: Vec<&str> = names.clone();
+        sorted_names.sort_unstable();
+        let keys: Vec<&str> = text.lines().map(|line| line.split_once('=').unwrap().0).collect();
+        assert_eq!(keys, sorted_names);
+        assert!(text.lines().next().unwrap().starts_with("alpha={\"alpha\":{\"alpha\":5,\"beta\":4,"));
+        assert_eq!(load_db::<Nested>(first_path).unwrap(), first);
+    }
+
+    #[test]
+    fn rewrites_read_the_format_from_the_file() {
+        let path = "target/test_db_preserved_format";
+
+        let _ = fs::remove_dir_all(path);
+
+        let blank: SaveOptions = options_preserving_format(path).unwrap();
+        assert!(!blank.compress && !blank.checksums && !blank.pretty && blank.schema_version.is_none());
+
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1)]);
+        for (compress, checksums, pretty) in [(true, true, true), (false, false, true), (true, false, false)] {
+            let options = SaveOptions { compress, checksums, pretty, schema_version: Some(3), ..SaveOptions::default() };
+            save_db_with_options(path, &db, &options).unwrap();
+            let preserved: SaveOptions = options_preserving_format(path).unwrap();
+            assert_eq!((preserved.compress, preserved.checksums, preserved.pretty), (compress, checksums, pretty));
+            assert_eq!(preserved.schema_version, Some(3));
+        }
+    }
+
+    #[test]
+    fn pretty_saves_back_up_swapped_nested_values() {
+        let path = "target/test_db_pretty_swap";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = SaveOptions { pretty: true, ..SaveOptions::default() };
+        let first: DB<serde_json::Value> = HashMap::from([("a".to_string(), serde_json::json!({"x": 1})), ("b".to_string(), serde_json::json!({"x": 2}))]);
+        let swapped: DB<serde_json::Value> = HashMap::from([("a".to_string(), serde_json::json!({"x": 2})), ("b".to_string(), serde_json::json!({"x": 1}))]);
+        save_db_with_options(path, &first, &options).unwrap();
+        save_db_with_options(path, &first, &options).unwrap();
+        assert!(list_backups(path).unwrap().is_empty());
+
+        save_db_with_options(path, &swapped, &options).unwrap();
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+        assert_eq!(load_db::<serde_json::Value>(path).unwrap(), swapped);
+    }
+
+    #[test]
+    fn best_effort_backups_do_not_block_saves() {
+        let path = "target/test_db_best_effort_backup";
+
+        let _ = fs::remove_dir_all(path);
+
+        save_db(path, &HashMap::from([("a".to_string(), 1)])).unwrap();
+        // A plain file where the backup directory should be makes every backup copy fail.
+        fs::write(get_backup_dir(path), "not a directory").unwrap();
+
+        let db: DB<u32> = HashMap::from([("a".to_string(), 2)]);
+        assert!(matches!(save_db(path, &db), Err(DBError::Io(_))));
+        assert_eq!(load_db::<u32>(path).unwrap()["a"], 1);
+
+        let options = SaveOptions { backup: BackupMode::BestEffort, ..SaveOptions::default() };
+        let report: SaveReport = save_with_reporting(path, &db, &options, &JsonCodec).unwrap();
+        assert_eq!(report.backup_path, None);
+        assert_eq!(load_db::<u32>(path).unwrap()["a"], 2);
+        assert!(!fs::exists(get_tmp_path(path)).unwrap());
+    }
+
+    #[test]
+    fn compressed_files_round_trip_and_are_smaller() {
+        let plain_path = "target/test_db_plain";
+        let compressed_path = "target/test_db_compressed";
+
+        let _ = fs::remove_dir_all(plain_path);
+        let _ = fs::remove_dir_all(compressed_path);
+
+        let mut db: DB<String> = HashMap::new();
+        for i in 0..300 {
+            db.insert(format!("key{}", i), format!("a fairly repetitive value number {}", i));
+        }
+
+        save_db(plain_path, &db).expect("plain save should succeed");
+        let options = SaveOptions { compress: true, force_backup: true, ..SaveOptions::default() };
+        save_db_with_options(compressed_path, &db, &options).expect("compressed save should succeed");
+        save_db_with_options(compressed_path, &db, &options).expect("compressed save should succeed");
+
+        let compressed_bytes = fs::read(get_db_path(compressed_path)).unwrap();
+        assert!(compressed_bytes.starts_with(&GZIP_MAGIC));
+        let plain_size = fs::metadata(get_db_path(plain_path)).unwrap().len();
+        assert!((compressed_bytes.len() as u64) < plain_size);
+
+        let loaded: DB<String> = load_db(compressed_path).expect("compressed load should succeed");
+        assert_eq!(loaded, db);
+        // The second save backed up the compressed file written by the first.
+        let restored: DB<String> = restore_latest_backup(compressed_path).expect("restore should succeed");
+        assert_eq!(restored, db);
+    }
+
+    #[test]
+    fn compression_is_detected_without_being_configured() {
+        let path = "target/test_db_compression_detected";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        save_db_with(path, &db, &DbConfig::new().compress(true)).unwrap();
+        assert!(fs::read(get_db_path(path)).unwrap().starts_with(&GZIP_MAGIC));
+        assert_eq!(load_db::<u32>(path).unwrap(), db);
+        assert_eq!(load_db_with::<u32, _>(path, &DbConfig::new()).unwrap(), db);
+        assert_eq!(load_db_stream::<u32>(path).collect::<Result<DB<u32>, DBError>>().unwrap(), db);
+
+        // Turning compression back off leaves a plain file that loads the same way.
+        save_db(path, &db).unwrap();
+        assert!(!fs::read(get_db_path(path)).unwrap().starts_with(&GZIP_MAGIC));
+        assert_eq!(load_db_with::<u32, _>(path, &DbConfig::new().compress(true)).unwrap(), db);
+    }
+
+    #[test]
+    fn byte_order_marks_are_skipped() {
+        let path = "target/test_db_bom";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+
+        let expected: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        fs::write(get_db_path(path), "\u{feff}a=1\nb=2\n").unwrap();
+        assert_eq!(load_db::<u32>(path).unwrap(), expected);
+        assert_eq!(load_db_strict::<u32>(path).unwrap(), expected);
+        assert_eq!(read_db::<u32, _>("\u{feff}a=1\nb=2\n".as_bytes()).unwrap(), expected);
+
+        // The mark mustn't hide the header that follows it.
+        save_db_with_options(path, &expected, &SaveOptions { checksums: true, ..SaveOptions::default() }).unwrap();
+        let saved: String = fs::read_to_string(get_db_path(path)).unwrap();
+        fs::write(get_db_path(path), format!("\u{feff}{}", saved)).unwrap();
+        assert_eq!(load_db::<u32>(path).unwrap(), expected);
+        assert_eq!(count_entries(path).unwrap(), 2);
+    }
+
+    #[test]
+    fn windows_line_endings_load() {
+        let path = "target/test_db_crlf";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+
+        let expected: DB<String> = HashMap::from([("a".to_string(), "x".to_string()), ("b".to_string(), "y".to_string())]);
+        fs::write(get_db_path(path), "a=\"x\"\r\n b = \"y\" \r\n\r\n").unwrap();
+        assert_eq!(load_db::<String>(path).unwrap(), expected);
+        assert_eq!(load_db_strict::<String>(path).unwrap(), expected);
+
+        save_db_with_options(path, &expected, &SaveOptions { checksums: true, pretty: true, ..SaveOptions::default() }).unwrap();
+        let saved: String = fs::read_to_string(get_db_path(path)).unwrap();
+        fs::write(get_db_path(path), saved.replace('\n', "\r\n")).unwrap();
+        assert_eq!(load_db::<String>(path).unwrap(), expected);
+        assert_eq!(load_db_strict::<String>(path).unwrap(), expected);
+    }
+
+    #[test]
+    fn concurrent_saves_are_serialized() {
+        let path = "target/test_db_concurrent_saves";
+
+        let _ = fs::remove_dir_all(path);
+
+        let handles: Vec<_> = (0..2).map(|writer| {
+            std::thread::spawn(move || {
+                let mut db: DB<u32> = HashMap::new();
+                for i in 0..200 {
+                    db.insert(format!("writer{}-{}", writer, i), i);
+                }
+                for _ in 0..10 {
+                    save_db_no_fsync(path, &db).expect("saving db should succeed");
+                }
+                db
+            })
+        }).collect();
+        let written: Vec<DB<u32>> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+        // Whichever writer went last, its save must have landed whole.
+        let loaded: DB<u32> = load_db(path).expect("loading db should succeed");
+        assert!(written.contains(&loaded));
+    }
+
+    #[test]
+    fn save_times_out_while_the_lock_is_held() {
+        let path = "target/test_db_lock_timeout";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+
+        let _held = FileLock::acquire(path, Duration::ZERO).expect("locking should succeed");
+        let options = SaveOptions { lock_timeout: Duration::from_millis(50), ..SaveOptions::default() };
+        let db: DB<u32> = HashMap::new();
+        let err = save_db_with_options(path, &db, &options).expect_err("save should time out");
+        assert!(matches!(err, DBError::LockTimeout(_)));
+    }
+
+    #[test]
+    fn error_variants_can_be_matched() {
+        let path = "target/test_db_error_variants";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(get_backup_dir(path)).unwrap();
+
+        fs::write(get_db_path(path), "no separator\n").unwrap();
+        let err = load_db::<u32>(path).unwrap_err();
+        assert!(matches!(err, DBError::MalformedLine { line: 1, ref content } if content == "no separator"));
+
+        fs::write(get_db_path(path), "k=not json\n").unwrap();
+        let err = load_db::<u32>(path).unwrap_err();
+        assert!(matches!(err, DBError::Serde(_)));
+        assert!(std::error::Error::source(&err).is_some());
+
+        fs::write(get_db_path(path), "%FF=1\n").unwrap();
+        assert!(matches!(load_db::<u32>(path).unwrap_err(), DBError::InvalidKeyEncoding(_)));
+
+        assert!(matches!(restore_latest_backup::<u32>(path).unwrap_err(), DBError::NotFound(_)));
+
+        fs::write(get_backup_dir(path).join("junk"), "").unwrap();
+        assert!(matches!(list_backups(path).unwrap_err(), DBError::BackupParse(ref name) if name == "junk"));
+
+        fs::remove_file(get_db_path(path)).unwrap();
+        fs::create_dir_all(get_db_path(path)).unwrap();
+        assert!(matches!(load_db::<u32>(path).unwrap_err(), DBError::Io(_)));
+    }
+
+    #[test]
+    fn unchanged_saves_do_not_create_backups() {
+        let path = "target/test_db_unchanged_saves";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        db.insert("b".to_string(), 2);
+        save_db(path, &db).expect("saving db should succeed");
+        // The first save has no earlier file to back up.
+        assert!(list_backups(path).unwrap().is_empty());
+
+        // Same entries in a different map, so possibly written in a different order.
+        let copy: DB<u32> = db.clone().into_iter().collect();
+        save_db(path, &db).expect("saving db should succeed");
+        save_db(path, &copy).expect("saving db should succeed");
+        assert!(list_backups(path).unwrap().is_empty());
+
+        let forced = SaveOptions { force_backup: true, ..SaveOptions::default() };
+        save_db_with_options(path, &db, &forced).expect("saving db should succeed");
+        assert_eq!(list_backups(path).unwrap().len(), 1);
+
+        db.insert("a".to_string(), 10);
+        save_db(path, &db).expect("saving db should succeed");
+        assert_eq!(list_backups(path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn duplicate_keys_resolve_last_wins_or_fail_strictly() {
+        let path = "target/test_db_duplicate_keys";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        fs::write(get_db_path(path), "k=1\nother=5\nk=2\n").unwrap();
+
+        let loaded: DB<u32> = load_db(path).expect("loading should succeed");
+        assert_eq!(loaded["k"], 2);
+        assert_eq!(get_one::<u32>(path, "k").unwrap(), Some(2));
+
+        let err = load_db_strict::<u32>(path).expect_err("strict load should reject duplicates");
+        assert!(matches!(err, DBError::DuplicateKey { ref key, ref lines } if key == "k" && *lines == vec![1, 3]));
+
+        fs::write(get_db_path(path), "k=1\nother=5\n").unwrap();
+        assert_eq!(load_db_strict::<u32>(path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let path = "target/test_db_comments";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        fs::write(
+            get_db_path(path),
+            "# user settings\n\ntheme=\"dark\"\n   \n  # indented comment\nsize=12\n#disabled=true\n",
+        ).unwrap();
+
+        let loaded: DB<serde_json::Value> = load_db_strict(path).expect("strict load should succeed");
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded["theme"], "dark");
+        assert_eq!(loaded["size"], 12);
+    }
+
+    #[test]
+    fn keys_starting_with_a_hash_round_trip() {
+        let path = "target/test_db_hash_keys";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("#tag".to_string(), 1);
+        db.insert("a#b".to_string(), 2);
+        save_db(path, &db).expect("saving should succeed");
+
+        assert_eq!(load_db::<u32>(path).unwrap(), db);
+    }
+
+    #[test]
+    fn saving_creates_missing_parent_directories() {
+        let root = "target/test_db_nested";
+        let path = "target/test_db_nested/data/sub/db";
+
+        let _ = fs::remove_dir_all(root);
+
+        let mut db: DB<u32> = HashMap::new();
+        db.insert("a".to_string(), 1);
+        save_db(path, &db).expect("saving to a nested path should succeed");
+        append_entry("target/test_db_nested/other/db", "b", &2).expect("appending should succeed");
+
+        assert_eq!(load_db::<u32>(path).unwrap(), db);
+        assert_eq!(get_one::<u32>("target/test_db_nested/other/db", "b").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn empty_path_means_the_current_directory() {
+        assert_eq!(get_db_path(""), "./memory.db");
+        assert_eq!(get_backup_dir(""), Path::new("./backups"));
+    }
+
+    #[test]
+    fn sorted_saves_are_byte_identical() {
+        let path = "target/test_db_sorted";
+
+        let _ = fs::remove_dir_all(path);
+
+        let first: DB<u32> = (0..100).map(|i| (format!("key{}", i), i)).collect();
+        save_db_sorted(path, &first).expect("saving should succeed");
+        let first_bytes = fs::read(get_db_path(path)).unwrap();
+
+        let second: DB<u32> = (0..100).rev().map(|i| (format!("key{}", i), i)).collect();
+        save_db_sorted(path, &second).expect("saving should succeed");
+        let second_bytes = fs::read(get_db_path(path)).unwrap();
+
+        assert_eq!(first_bytes, second_bytes);
+        let contents = String::from_utf8(first_bytes).unwrap();
+        let keys: Vec<&str> = contents.lines().map(|line| line.split_once('=').unwrap().0).collect();
+        assert!(keys.is_sorted());
+    }
+
+    #[test]
+    fn prefix_and_range_scans() {
+        let path = "target/test_db_scans";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        // The order line has a value that isn't a u32: it must never be deserialized.
+        fs::write(get_db_path(path), "user:1=10\norder:1=\"not a u32\"\nuser:2=20\nuser:3=30\n").unwrap();
+
+        let users: DB<u32> = scan_prefix(path, "user:").expect("prefix scan should succeed");
+        assert_eq!(users.len(), 3);
+        assert_eq!(users["user:1"], 10);
+        assert_eq!(users["user:2"], 20);
+
+        let range: DB<u32> = scan_range(path, "user:1", "user:3").expect("range scan should succeed");
+        let mut keys: Vec<&String> = range.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["user:1", "user:2"]);
+
+        assert!(scan_prefix::<u32>(path, "missing:").unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sync_dir_errors_are_reported() {
+        let result = sync_dir(Path::new("target/test_db_sync_missing/nope"));
+
+        let err = result.expect_err("syncing a missing directory should fail");
+        assert!(matches!(err, DBError::Io(_)));
     }
 }
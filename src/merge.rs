@@ -0,0 +1,95 @@
+use std::collections::hash_map::Entry;
+
+use crate::DB;
+
+/// Combines an existing and an incoming value for the same key, called as `resolve(existing, incoming)`.
+pub type Resolver<T> = Box<dyn Fn(&T, &T) -> T>;
+
+/// How `merge` resolves a key present in both databases.
+pub enum MergeStrategy<T> {
+    /// Keep the value already in the target.
+    PreferExisting,
+    /// Take the incoming value.
+    PreferIncoming,
+    /// Combine the two with a `Resolver`.
+    Custom(Resolver<T>),
+}
+
+impl<T> std::fmt::Debug for MergeStrategy<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeStrategy::PreferExisting => write!(f, "PreferExisting"),
+            MergeStrategy::PreferIncoming => write!(f, "PreferIncoming"),
+            MergeStrategy::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// Moves every entry of `from` into `into`. Keys only in `from` are added as-is; keys in both are
+/// resolved by `strategy`.
+pub fn merge<T>(into: &mut DB<T>, from: DB<T>, strategy: &MergeStrategy<T>) {
+    merge_changed(into, from, strategy);
+}
+
+// Like `merge`, returning whether `into` changed, so `Database` knows whether it's dirty.
+pub(crate) fn merge_changed<T>(into: &mut DB<T>, from: DB<T>, strategy: &MergeStrategy<T>) -> bool {
+    let mut changed: bool = false;
+    for (key, incoming) in from {
+        match into.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(incoming);
+                changed = true;
+            }
+            Entry::Occupied(mut entry) => match strategy {
+                MergeStrategy::PreferExisting => {}
+                MergeStrategy::PreferIncoming => {
+                    entry.insert(incoming);
+                    changed = true;
+                }
+                MergeStrategy::Custom(resolve) => {
+                    let resolved: T = resolve(entry.get(), &incoming);
+                    entry.insert(resolved);
+                    changed = true;
+                }
+            },
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn databases() -> (DB<u32>, DB<u32>) {
+        let existing: DB<u32> = HashMap::from([("a".to_string(), 1), ("both".to_string(), 10)]);
+        let incoming: DB<u32> = HashMap::from([("b".to_string(), 2), ("both".to_string(), 20)]);
+        (existing, incoming)
+    }
+
+    #[test]
+    fn prefer_existing_and_incoming() {
+        let (mut into, from) = databases();
+        merge(&mut into, from, &MergeStrategy::PreferExisting);
+        assert_eq!(into, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2), ("both".to_string(), 10)]));
+
+        let (mut into, from) = databases();
+        merge(&mut into, from, &MergeStrategy::PreferIncoming);
+        assert_eq!(into, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2), ("both".to_string(), 20)]));
+    }
+
+    #[test]
+    fn custom_resolver_combines_conflicts() {
+        let (mut into, from) = databases();
+        merge(&mut into, from, &MergeStrategy::Custom(Box::new(|existing, incoming| existing + incoming)));
+        assert_eq!(into, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2), ("both".to_string(), 30)]));
+
+        // Disjoint keys never reach the resolver.
+        let mut into: DB<u32> = HashMap::from([("x".to_string(), 1)]);
+        let from: DB<u32> = HashMap::from([("y".to_string(), 2)]);
+        assert!(merge_changed(&mut into, from, &MergeStrategy::Custom(Box::new(|_, _| unreachable!()))));
+        assert_eq!(into.len(), 2);
+        assert!(!merge_changed(&mut into, HashMap::from([("x".to_string(), 5)]), &MergeStrategy::PreferExisting));
+    }
+}
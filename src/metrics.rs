@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::SaveReport;
+
+/// What a `Database` handle has done since it was opened, as returned by `Database::metrics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Times the map was read from disk: once on `open`, then once per reload by `watch`.
+    pub loads: u64,
+    /// Saves that actually wrote the file. Calls to `save` with nothing to write don't count.
+    pub saves: u64,
+    /// Calls to `insert`, including those made by `extend` and `get_or_insert_with`, and values
+    /// set by transactions. Changes made through `entry` aren't counted.
+    pub inserts: u64,
+    /// Entries removed by `remove`, `clear`, eviction and transactions.
+    pub removes: u64,
+    /// The total size of the data files written by those saves.
+    pub bytes_written: u64,
+    pub backups_created: u64,
+}
+
+// The live counters behind `MetricsSnapshot`. Atomic so they can be bumped through `&self`; each
+// counter is independent, so relaxed ordering is enough.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    loads: AtomicU64,
+    saves: AtomicU64,
+    inserts: AtomicU64,
+    removes: AtomicU64,
+    bytes_written: AtomicU64,
+    backups_created: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn record_load(&self) {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_save(&self, report: &SaveReport) {
+        self.saves.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(report.bytes_written as u64, Ordering::Relaxed);
+        if report.backup_path.is_some() {
+            self.backups_created.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_insert(&self) {
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_removes(&self, count: u64) {
+        self.removes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            loads: self.loads.load(Ordering::Relaxed),
+            saves: self.saves.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            removes: self.removes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            backups_created: self.backups_created.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{get_db_path, Database};
+
+    #[test]
+    fn counters_follow_operations() {
+        let path = "target/test_metrics";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        assert_eq!(db.metrics(), MetricsSnapshot { loads: 1, ..MetricsSnapshot::default() });
+
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        db.save().unwrap();
+        let first_size: u64 = fs::metadata(get_db_path(path)).unwrap().len();
+        db.save().unwrap();
+
+        db.remove("a");
+        db.remove("missing");
+        db.insert("c", 3).unwrap();
+        db.clear();
+        db.save().unwrap();
+
+        let metrics: MetricsSnapshot = db.metrics();
+        assert_eq!((metrics.loads, metrics.saves, metrics.inserts, metrics.removes), (1, 2, 3, 3));
+        // Only the second save had a previous file to back up; it wrote an empty one.
+        assert_eq!(metrics.backups_created, 1);
+        assert_eq!(metrics.bytes_written, first_size);
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{load_db, DBError, DB};
+
+/// A value with the time it was last written, stored on disk as
+/// `{"modified_at": "<rfc3339>", "value": ...}`. This is how a `Database` with
+/// `DatabaseOptions::track_modified` saves its values, so such a file also loads as
+/// `DB<Modified<T>>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Modified<T> {
+    pub modified_at: DateTime<FixedOffset>,
+    pub value: T,
+}
+
+// When each key was last written, kept by `Database` alongside its map.
+pub(crate) type ModifiedTimes = HashMap<String, DateTime<FixedOffset>>;
+
+// Loads the map at `path`, along with the times its values were written if they're `tracked`.
+pub(crate) fn load_tracked<T>(path: &str, tracked: bool) -> Result<(DB<T>, ModifiedTimes), DBError> where T: DeserializeOwned {
+    if !tracked {
+        return Ok((load_db(path)?, ModifiedTimes::new()));
+    }
+    let stamped: DB<Modified<T>> = load_db(path)?;
+    let mut data: DB<T> = DB::with_capacity(stamped.len());
+    let mut times: ModifiedTimes = ModifiedTimes::with_capacity(stamped.len());
+    for (key, Modified { modified_at, value }) in stamped {
+        times.insert(key.clone(), modified_at);
+        data.insert(key, value);
+    }
+    Ok((data, times))
+}
+
+// The map `Database` saves when tracking times. Every key in `data` must have a time.
+pub(crate) fn stamp<'a, T>(data: &'a DB<T>, times: &ModifiedTimes) -> DB<Modified<&'a T>> {
+    data.iter().map(|(key, value)| (key.clone(), Modified { modified_at: times[key], value })).collect()
+}
+
+pub(crate) fn now() -> DateTime<FixedOffset> {
+    chrono::Local::now().fixed_offset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+    use crate::{Database, DatabaseOptions};
+
+    #[test]
+    fn inserts_record_when_they_happened() {
+        let path = "target/test_modified_at";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { track_modified: true, ..DatabaseOptions::default() };
+        let mut db: Database<u32> = Database::open_with_options(path, options.clone()).unwrap();
+        let before: DateTime<FixedOffset> = now();
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        let first: DateTime<FixedOffset> = db.modified_at("a").unwrap();
+        assert!(before <= first && first <= now());
+
+        thread::sleep(Duration::from_millis(5));
+        db.insert("a", 3).unwrap();
+        let second: DateTime<FixedOffset> = db.modified_at("a").unwrap();
+        assert!(second > first);
+        assert_eq!(db.modified_at("missing"), None);
+        db.save().unwrap();
+
+        let stored: DB<Modified<u32>> = load_db(path).unwrap();
+        assert_eq!(stored["a"], Modified { modified_at: second, value: 3 });
+        let mut db: Database<u32> = Database::open_with_options(path, options).unwrap();
+        assert_eq!(db.modified_at("a"), Some(second));
+        assert_eq!(db.get("b"), Some(&2));
+
+        db.remove("a");
+        assert_eq!(db.modified_at("a"), None);
+        assert_eq!(Database::<u32>::in_memory().modified_at("a"), None);
+    }
+}
@@ -0,0 +1,60 @@
+use rayon::prelude::*;
+use serde::ser::Serialize;
+
+use crate::schema::read_schema_version;
+use crate::{commit, encode_value, ensure_db_dir, push_record, validate_keys, write_headers, DBError, FileLock, JsonCodec, SaveOptions, DB};
+
+/// Like `save_db_sorted`, but serializes the values on all cores before writing the lines out
+/// in key order, so the file is byte-for-byte what `save_db_sorted` writes. Worth it when
+/// values are expensive to serialize; the write itself is still sequential. Serialization
+/// happens before the lock is taken, so other savers aren't held up by it.
+pub fn save_db_parallel<T>(path: &str, contents: &DB<T>) -> Result<(), DBError> where T: Serialize + Sync {
+    let mut options = SaveOptions { sorted: true, ..SaveOptions::default() };
+    validate_keys(contents, &options)?;
+    let mut entries: Vec<(&String, &T)> = contents.iter().collect();
+    entries.par_sort_unstable_by_key(|(key, _)| *key);
+    let lines: Vec<String> = entries.par_iter()
+        .map(|(key, value)| {
+            let mut line: String = String::new();
+            push_record(&mut line, key, &encode_value(&JsonCodec, key, value)?, false);
+            Ok(line)
+        })
+        .collect::<Result<_, DBError>>()?;
+
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, options.lock_timeout)?;
+    options.schema_version = read_schema_version(path)?;
+    commit(path, &options, |writer| {
+        write_headers(writer, &options)?;
+        for line in &lines {
+            writer.write_all(line.as_bytes())?;
+        }
+        Ok(lines.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::{get_db_path, load_db, save_db_sorted};
+
+    #[test]
+    fn parallel_and_sequential_saves_match() {
+        let sequential = "target/test_parallel_sequential";
+        let parallel = "target/test_parallel";
+
+        let _ = fs::remove_dir_all(sequential);
+        let _ = fs::remove_dir_all(parallel);
+
+        let db: DB<HashMap<String, Vec<u32>>> = (0..5_000)
+            .map(|i| (format!("key{}", i), HashMap::from([("values".to_string(), (0..i % 50).collect())])))
+            .collect();
+        save_db_sorted(sequential, &db).unwrap();
+        save_db_parallel(parallel, &db).unwrap();
+
+        assert_eq!(fs::read(get_db_path(parallel)).unwrap(), fs::read(get_db_path(sequential)).unwrap());
+        assert_eq!(load_db::<HashMap<String, Vec<u32>>>(parallel).unwrap(), db);
+    }
+}
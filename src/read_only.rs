@@ -0,0 +1,90 @@
+use std::collections::hash_map;
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+
+use crate::{load_db, DBError, DB};
+
+/// A handle that can only read a database. It has no `insert`, `remove` or `save`, so writes are
+/// ruled out at compile time, and opening it never touches the file beyond reading it: no lock,
+/// no directory creation, no backups.
+#[derive(Debug)]
+pub struct ReadOnlyDatabase<T> {
+    path: PathBuf,
+    data: DB<T>,
+}
+
+impl<T> ReadOnlyDatabase<T> where T: DeserializeOwned {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DBError> {
+        let path = path.as_ref().to_path_buf();
+        let data = load_db(path.to_str().ok_or_else(|| DBError::InvalidPath(path.clone()))?)?;
+        Ok(ReadOnlyDatabase { path, data })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.data.get(key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.data.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Iterates over the entries in arbitrary order.
+    pub fn iter(&self) -> hash_map::Iter<'_, String, T> {
+        self.data.iter()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<'a, T> IntoIterator for &'a ReadOnlyDatabase<T> {
+    type Item = (&'a String, &'a T);
+    type IntoIter = hash_map::Iter<'a, String, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::save_db;
+
+    #[test]
+    fn reads_work_without_touching_the_disk() {
+        let path = "target/test_read_only";
+
+        let _ = fs::remove_dir_all(path);
+
+        let missing: ReadOnlyDatabase<u32> = ReadOnlyDatabase::open(path).unwrap();
+        assert!(missing.is_empty());
+        assert!(!fs::exists(path).unwrap());
+
+        save_db(path, &HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)])).unwrap();
+        let files_before = fs::read_dir(path).unwrap().count();
+
+        let db: ReadOnlyDatabase<u32> = ReadOnlyDatabase::open(path).unwrap();
+        assert_eq!(db.get("a"), Some(&1));
+        assert!(db.contains("b"));
+        assert!(!db.contains("c"));
+        assert_eq!(db.len(), 2);
+        let sum: u32 = db.iter().map(|(_, value)| value).sum();
+        assert_eq!(sum, 3);
+        assert_eq!((&db).into_iter().count(), 2);
+
+        drop(db);
+        assert_eq!(fs::read_dir(path).unwrap().count(), files_before);
+    }
+}
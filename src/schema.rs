@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use serde::de::DeserializeOwned;
+
+use crate::stream::open_reader;
+use crate::{read_db_file, records, DBError, CHECKSUM_HEADER, DB, PRETTY_HEADER, SCHEMA_HEADER_PREFIX};
+
+/// Upgrades one record from a schema version to the next, given its key and its value as raw
+/// JSON; see `load_db_migrated`.
+pub type Migration = fn(String, serde_json::Value) -> (String, serde_json::Value);
+
+/// Loads the database at `path`, first bringing every record up to the latest schema version by
+/// running the migrations the file hasn't had yet, in order. `migrations[0]` upgrades version 1
+/// to 2, `migrations[1]` version 2 to 3, and so on; a file without a `#schema=N` header is at
+/// version 1. A file newer than the last migration fails with `DBError::WrongFormat`.
+///
+/// The file itself isn't changed. Save the result with `SaveOptions::schema_version` set to
+/// `migrations.len() + 1` to record that it's been migrated; later saves keep that version.
+pub fn load_db_migrated<T>(path: &str, migrations: &[Migration]) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    let contents = read_db_file(path)?;
+    let version: u32 = parse_schema_version(contents.lines())?.unwrap_or(1);
+    let latest: u32 = migrations.len() as u32 + 1;
+    if version > latest {
+        return Err(DBError::WrongFormat(format!("schema version {} is newer than the latest known, {}", version, latest)));
+    }
+    let pending: &[Migration] = &migrations[version as usize - 1..];
+    let mut db: DB<T> = HashMap::new();
+    for record in records(&contents, false) {
+        let record = record?;
+        let (mut key, mut value): (String, serde_json::Value) = (record.key, serde_json::from_str(record.value)?);
+        for migration in pending {
+            (key, value) = migration(key, value);
+        }
+        db.insert(key, serde_json::from_value(value)?);
+    }
+    Ok(db)
+}
+
+// The schema version declared by the data file at `path`, reading no further than its header.
+pub(crate) fn read_schema_version(path: &str) -> Result<Option<u32>, DBError> {
+    match open_reader(path) {
+        Ok(Some(reader)) => parse_schema_version(reader.lines().take(3).map_while(Result::ok)),
+        // A binary database has no header, and its format is being replaced anyway.
+        Ok(None) | Err(DBError::WrongFormat(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// The `#schema=N` header comes after the checksum and pretty headers, when there are any.
+pub(crate) fn parse_schema_version<I, S>(mut lines: I) -> Result<Option<u32>, DBError> where I: Iterator<Item = S>, S: AsRef<str> {
+    let mut line = lines.next();
+    if line.as_ref().is_some_and(|line| line.as_ref() == CHECKSUM_HEADER) {
+        line = lines.next();
+    }
+    if line.as_ref().is_some_and(|line| line.as_ref() == PRETTY_HEADER) {
+        line = lines.next();
+    }
+    let Some(version) = line.as_ref().and_then(|line| line.as_ref().strip_prefix(SCHEMA_HEADER_PREFIX)) else {
+        return Ok(None);
+    };
+    match version.parse::<u32>() {
+        Ok(version) if version >= 1 => Ok(Some(version)),
+        _ => Err(DBError::WrongFormat(format!("invalid schema header {}{}", SCHEMA_HEADER_PREFIX, version))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use serde::{Deserialize, Serialize};
+    use crate::{compact, get_db_path, save_db, save_db_with_options, SaveOptions};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct UserV1 {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct UserV2 {
+        full_name: String,
+    }
+
+    fn rename_name_field(key: String, mut value: serde_json::Value) -> (String, serde_json::Value) {
+        if let Some(fields) = value.as_object_mut() && let Some(name) = fields.remove("name") {
+            fields.insert("full_name".to_string(), name);
+        }
+        (key, value)
+    }
+
+    #[test]
+    fn v1_files_are_migrated_on_load() {
+        let path = "target/test_schema_migrate";
+        let migrations: &[Migration] = &[rename_name_field];
+
+        let _ = fs::remove_dir_all(path);
+
+        let v1: DB<UserV1> = HashMap::from([("ada".to_string(), UserV1 { name: "Ada Lovelace".to_string() })]);
+        save_db(path, &v1).unwrap();
+
+        let migrated: DB<UserV2> = load_db_migrated(path, migrations).unwrap();
+        assert_eq!(migrated["ada"], UserV2 { full_name: "Ada Lovelace".to_string() });
+
+        let options = SaveOptions { schema_version: Some(2), checksums: true, ..SaveOptions::default() };
+        save_db_with_options(path, &migrated, &options).unwrap();
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert!(contents.starts_with("#memory_db:crc32\n#schema=2\n"));
+
+        // Saves that don't set a version keep the file's, so the data isn't migrated twice.
+        compact(path).unwrap();
+        save_db(path, &migrated).unwrap();
+        assert_eq!(read_schema_version(path).unwrap(), Some(2));
+        let reloaded: DB<UserV2> = load_db_migrated(path, migrations).unwrap();
+        assert_eq!(reloaded, migrated);
+
+        assert!(matches!(load_db_migrated::<UserV1>(path, &[]), Err(DBError::WrongFormat(_))));
+    }
+}
@@ -0,0 +1,156 @@
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{encode_key_escaping, parse_record, read_db_file, value_checksum, Codec, DBError, SaveOptions, CHECKSUM_HEADER, DB};
+
+// Files written with separators other than `\n` and `=` are split on them directly, with
+// none of the line-based extras: there are no continuation lines, and the schema header isn't
+// looked for, so `SaveOptions::pretty` and `schema_version` can't be combined with them.
+
+impl SaveOptions {
+    pub(crate) fn has_custom_separators(&self) -> bool {
+        self.record_separator != "\n" || self.kv_separator != "="
+    }
+
+    pub(crate) fn check_separators(&self) -> Result<(), DBError> {
+        let invalid = |reason: &str| Err(DBError::InvalidConfig(reason.to_string()));
+        if self.record_separator.is_empty() || self.kv_separator.is_empty() {
+            return invalid("separators can't be empty");
+        }
+        if self.record_separator == self.kv_separator {
+            return invalid("record_separator and kv_separator must differ");
+        }
+        // These would be confused with the `%XX` key escapes or the `#crc` checksum suffix.
+        let reserved = |c: char| c == '%' || c == '#' || c.is_ascii_hexdigit();
+        if self.record_separator.contains(reserved) || self.kv_separator.contains(reserved) {
+            return invalid("separators can't contain '%', '#' or hex digits");
+        }
+        if self.has_custom_separators() && self.pretty {
+            return invalid("pretty can't be combined with custom separators");
+        }
+        if self.has_custom_separators() && self.schema_version.is_some() {
+            return invalid("schema_version can't be combined with custom separators");
+        }
+        Ok(())
+    }
+}
+
+// Like `format_record`, with the separators from `options`. Key characters that occur in either
+// separator are percent-encoded; a value only has to stay clear of the record separator, so it
+// may contain line breaks.
+pub(crate) fn format_separated<T, C>(codec: &C, key: &str, value: &T, options: &SaveOptions) -> Result<String, DBError> where T: Serialize, C: Codec {
+    let separators: String = format!("{}{}", options.record_separator, options.kv_separator);
+    let raw_key: String = encode_key_escaping(key, |c| separators.contains(c));
+    let mut value: String = codec.encode(value)?;
+    if value.contains(options.record_separator.as_str()) {
+        return Err(DBError::InvalidValue { key: key.to_string(), reason: "serialized value contains the record separator".to_string() });
+    }
+    if options.checksums {
+        value = format!("{}#{}", value, value_checksum(&value));
+    }
+    Ok(format!("{}{}{}{}", raw_key, options.kv_separator, value, options.record_separator))
+}
+
+// The `load_db_with` counterpart of `format_separated`. Record numbers stand in for line numbers
+// in errors.
+pub(crate) fn load_separated<T, C>(path: &str, options: &SaveOptions, codec: &C) -> Result<DB<T>, DBError> where T: DeserializeOwned, C: Codec {
+    let contents = read_db_file(path)?;
+    let mut records = contents.split(options.record_separator.as_str()).enumerate().peekable();
+    let checksummed: bool = records.next_if(|(_, record)| record.trim() == CHECKSUM_HEADER).is_some();
+    let mut db: DB<T> = DB::new();
+    for (index, record) in records {
+        if let Some(record) = parse_record(index, record, false, checksummed, &options.kv_separator) {
+            let record = record?;
+            db.insert(record.key, codec.decode(record.value)?);
+        }
+    }
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use crate::{get_db_path, load_db_with, save_db_with, DbConfig, KeyValidator};
+
+    #[test]
+    fn record_separator_control_char_round_trip() {
+        let path = "target/test_separators_round_trip";
+
+        let _ = fs::remove_dir_all(path);
+
+        let config = DbConfig::new().record_separator("\x1e").kv_separator(": ").checksums(true).build().unwrap();
+        let db: DB<String> = HashMap::from([
+            ("plain".to_string(), "value".to_string()),
+            ("key: with separators\x1e".to_string(), "a=b, c: d".to_string()),
+            ("tab\tkey".to_string(), "x".to_string()),
+        ]);
+        save_db_with(path, &db, &config).unwrap();
+
+        let contents = fs::read_to_string(get_db_path(path)).unwrap();
+        assert!(contents.starts_with("#memory_db:crc32\x1e"));
+        assert!(contents.contains("plain: \"value\"#"));
+        assert_eq!(contents.matches('\x1e').count(), 4);
+        assert_eq!(load_db_with::<String, _>(path, &config).unwrap(), db);
+    }
+
+    #[test]
+    fn custom_separators_drop_an_existing_schema_header() {
+        let path = "target/test_separators_schema_header";
+
+        let _ = fs::remove_dir_all(path);
+
+        crate::save_db_with_options(path, &DB::from([("old".to_string(), 0)]), &SaveOptions { schema_version: Some(2), ..SaveOptions::default() }).unwrap();
+        let config = DbConfig::new().record_separator("\x1e").build().unwrap();
+        let db: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        save_db_with(path, &db, &config).unwrap();
+
+        assert!(!fs::read_to_string(get_db_path(path)).unwrap().contains("#schema"));
+        assert_eq!(load_db_with::<u32, _>(path, &config).unwrap(), db);
+    }
+
+    #[test]
+    fn keys_are_checked_against_custom_separators() {
+        let path = "target/test_separators_keys";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = HashMap::from([("a;b".to_string(), 1), ("c:d".to_string(), 2), ("e=f".to_string(), 3)]);
+        let config = DbConfig::new().record_separator(";").kv_separator(":").build().unwrap();
+        save_db_with(path, &db, &config).unwrap();
+        assert_eq!(load_db_with::<u32, _>(path, &config).unwrap(), db);
+
+        let validator = KeyValidator { reject_separators: true, ..KeyValidator::default() };
+        let config = config.key_validator(validator).build().unwrap();
+        let err = save_db_with(path, &db, &config).unwrap_err();
+        assert!(matches!(err, DBError::InvalidKey { key, .. } if key != "e=f"));
+        // `=` is only a separator by default.
+        save_db_with(path, &HashMap::from([("e=f".to_string(), 3)]), &config).unwrap();
+    }
+
+    #[test]
+    fn bad_separators_are_rejected() {
+        for config in [
+            DbConfig::new().record_separator(""),
+            DbConfig::new().kv_separator(""),
+            DbConfig::new().record_separator(";").kv_separator(";"),
+            DbConfig::new().record_separator(";").pretty(true),
+            DbConfig::new().kv_separator("%"),
+            DbConfig::new().kv_separator("4"),
+            DbConfig::new().kv_separator(":e:"),
+            DbConfig::new().kv_separator("#"),
+            DbConfig::new().record_separator("\nF"),
+            DbConfig::new().record_separator("%%"),
+            DbConfig::new().record_separator("\u{1e}#"),
+        ] {
+            assert!(matches!(config.build(), Err(DBError::InvalidConfig(_))));
+        }
+        for kv_separator in ["\n", "%", "4"] {
+            let options = SaveOptions { kv_separator: kv_separator.to_string(), ..SaveOptions::default() };
+            let db: DB<u32> = DB::from([("50%".to_string(), 1), ("x4".to_string(), 2)]);
+            let err = crate::save_db_with_options("target/test_separators_rejected", &db, &options).unwrap_err();
+            assert!(matches!(err, DBError::InvalidConfig(_)));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{DBError, Database};
+
+/// A `Database` that can be cloned and shared across threads. Reads take a shared lock and run
+/// concurrently; writes and saves are serialized.
+///
+/// If a thread panics while holding the lock, the lock is poisoned and every later call
+/// returns a `DBError` rather than operating on possibly half-updated state.
+#[derive(Debug)]
+pub struct SharedDatabase<T> where T: Serialize {
+    inner: Arc<RwLock<Database<T>>>,
+}
+
+impl<T> Clone for SharedDatabase<T> where T: Serialize {
+    fn clone(&self) -> Self {
+        SharedDatabase { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl<T> From<Database<T>> for SharedDatabase<T> where T: Serialize {
+    fn from(db: Database<T>) -> Self {
+        SharedDatabase { inner: Arc::new(RwLock::new(db)) }
+    }
+}
+
+impl<T> SharedDatabase<T> where T: Serialize + DeserializeOwned {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DBError> {
+        Ok(Database::open(path)?.into())
+    }
+
+    /// Returns a clone of the value so the lock isn't held while the caller uses it.
+    pub fn get(&self, key: &str) -> Result<Option<T>, DBError> where T: Clone {
+        Ok(self.read()?.get(key).cloned())
+    }
+
+    pub fn insert(&self, key: impl Into<String>, value: T) -> Result<Option<T>, DBError> {
+        self.write()?.insert(key, value)
+    }
+
+    pub fn remove(&self, key: &str) -> Result<Option<T>, DBError> {
+        Ok(self.write()?.remove(key))
+    }
+
+    pub fn len(&self) -> Result<usize, DBError> {
+        Ok(self.read()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, DBError> {
+        Ok(self.read()?.is_empty())
+    }
+
+    pub fn save(&self) -> Result<(), DBError> {
+        self.write()?.save()
+    }
+
+    pub(crate) fn read(&self) -> Result<RwLockReadGuard<'_, Database<T>>, DBError> {
+        self.inner.read().map_err(|_| DBError::LockPoisoned)
+    }
+
+    pub(crate) fn write(&self) -> Result<RwLockWriteGuard<'_, Database<T>>, DBError> {
+        self.inner.write().map_err(|_| DBError::LockPoisoned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn concurrent_inserts_and_reads() {
+        let path = "target/test_shared_concurrent";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: SharedDatabase<u32> = SharedDatabase::open(path).expect("opening should succeed");
+        let handles: Vec<_> = (0..8).map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let key = format!("{}-{}", t, i);
+                    db.insert(key.clone(), i).unwrap();
+                    assert_eq!(db.get(&key).unwrap(), Some(i));
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(db.len().unwrap(), 800);
+        db.save().expect("saving should succeed");
+        let reopened: SharedDatabase<u32> = SharedDatabase::open(path).unwrap();
+        assert_eq!(reopened.len().unwrap(), 800);
+    }
+
+    #[test]
+    fn poisoned_lock_is_reported_as_an_error() {
+        let path = "target/test_shared_poisoned";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: SharedDatabase<u32> = SharedDatabase::open(path).unwrap();
+        let poisoner = db.clone();
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.inner.write().unwrap();
+            panic!("poison the lock");
+        }).join();
+
+        let err = db.get("a").expect_err("a poisoned lock should be an error");
+        assert!(matches!(err, DBError::LockPoisoned));
+    }
+}
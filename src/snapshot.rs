@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+
+use crate::{get_db_dir, get_db_path, parse_db, read_file_contents, sync_dir, DBError, JsonCodec, DB};
+
+// Snapshots live in "<db_dir>/snapshots", next to but separate from "backups", so backup pruning
+// never sees them.
+fn get_snapshot_dir(path: &str) -> PathBuf {
+    Path::new(get_db_dir(path)).join("snapshots")
+}
+
+// Labels become file names, so they're limited to characters that are safe on every platform,
+// and can't start with `.`, which keeps out `.`, `..`, hidden files and the temp files below.
+fn validate_label(label: &str) -> Result<(), DBError> {
+    let invalid = |reason: &str| Err(DBError::InvalidLabel { label: label.to_string(), reason: reason.to_string() });
+    if label.is_empty() {
+        return invalid("labels can't be empty");
+    }
+    if label.starts_with('.') {
+        return invalid("labels can't start with '.'");
+    }
+    if !label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return invalid("labels may only contain ASCII letters, digits, '-', '_' and '.'");
+    }
+    Ok(())
+}
+
+/// Copies the data file at `path` to `snapshots/<label>`, byte for byte, replacing any snapshot
+/// with the same label. Unlike backups, snapshots are only ever created and removed by the caller.
+///
+/// Saves replace the data file atomically, so the copy is always of one complete save.
+pub fn snapshot(path: &str, label: &str) -> Result<(), DBError> {
+    validate_label(label)?;
+    let file_path = get_db_path(path);
+    if !Path::new(&file_path).exists() {
+        return Err(DBError::NotFound(format!("database file {}", file_path)));
+    }
+    let snapshot_dir = get_snapshot_dir(path);
+    fs::create_dir_all(&snapshot_dir)?;
+    // Copied to a temp file first, so a crash mid-copy can't leave a truncated snapshot behind.
+    let tmp_path = snapshot_dir.join(format!(".{}.tmp", label));
+    fs::copy(&file_path, &tmp_path)?;
+    fs::File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, snapshot_dir.join(label))?;
+    sync_dir(&snapshot_dir)
+}
+
+/// Loads the snapshot taken under `label`, parsing it the same way `load_db` parses the main
+/// file. The data file itself is left alone; save the result to roll it back.
+pub fn restore_snapshot<T>(path: &str, label: &str) -> Result<DB<T>, DBError> where T: DeserializeOwned {
+    validate_label(label)?;
+    let contents = match read_file_contents(&get_snapshot_dir(path).join(label)) {
+        Ok(contents) => contents,
+        Err(DBError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(DBError::NotFound(format!("snapshot {}", label)));
+        }
+        Err(e) => return Err(e),
+    };
+    parse_db(&contents, false, &JsonCodec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{load_db, save_db, save_db_with_options, BackupPolicy, SaveOptions};
+
+    #[test]
+    fn snapshot_then_restore_after_changes() {
+        let path = "target/test_snapshot_restore";
+
+        let _ = fs::remove_dir_all(path);
+        assert!(matches!(snapshot(path, "empty"), Err(DBError::NotFound(_))));
+
+        let before: DB<u32> = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        save_db(path, &before).unwrap();
+        snapshot(path, "before-migration").unwrap();
+
+        let options = SaveOptions { backup_policy: BackupPolicy::KeepCount(1), force_backup: true, ..SaveOptions::default() };
+        for i in 0..3 {
+            let after: DB<u32> = HashMap::from([("c".to_string(), i)]);
+            save_db_with_options(path, &after, &options).unwrap();
+        }
+        assert_eq!(load_db::<u32>(path).unwrap().len(), 1);
+
+        // Pruning down to one backup left the snapshot alone.
+        let restored: DB<u32> = restore_snapshot(path, "before-migration").unwrap();
+        assert_eq!(restored, before);
+        assert!(matches!(restore_snapshot::<u32>(path, "missing"), Err(DBError::NotFound(_))));
+    }
+
+    #[test]
+    fn unsafe_labels_are_rejected() {
+        let path = "target/test_snapshot_labels";
+
+        let _ = fs::remove_dir_all(path);
+        save_db(path, &HashMap::from([("a".to_string(), 1)])).unwrap();
+
+        for label in ["", ".", "..", "../escape", "a/b", "a\\b", ".hidden", "with space"] {
+            assert!(matches!(snapshot(path, label), Err(DBError::InvalidLabel { .. })), "{:?} should be rejected", label);
+        }
+        assert!(!Path::new(path).join("escape").exists());
+        snapshot(path, "v1.2_ok-label").unwrap();
+    }
+}
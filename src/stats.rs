@@ -0,0 +1,71 @@
+use std::fs;
+use chrono::{DateTime, FixedOffset};
+
+use crate::{count_entries, get_db_path, list_backups, DBError};
+
+/// On-disk statistics for a database, returned by `stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub entry_count: usize,
+    /// The size of the data file as stored, i.e. after compression, if any.
+    pub file_size_bytes: u64,
+    pub backup_count: usize,
+    pub oldest_backup: Option<DateTime<FixedOffset>>,
+    pub newest_backup: Option<DateTime<FixedOffset>>,
+}
+
+/// Gathers `Stats` for the database at `path`. A database that was never saved reports zero
+/// entries and a size of 0. Backups are only looked for in the default `backups/` directory.
+///
+/// Like `count_entries`, this reads the whole file, and fails on a binary database.
+pub fn stats(path: &str) -> Result<Stats, DBError> {
+    let file_size_bytes: u64 = match fs::metadata(get_db_path(path)) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    let backups: Vec<DateTime<FixedOffset>> = list_backups(path)?;
+    Ok(Stats {
+        entry_count: count_entries(path)?,
+        file_size_bytes,
+        backup_count: backups.len(),
+        oldest_backup: backups.first().copied(),
+        newest_backup: backups.last().copied(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{save_db, save_db_with_options, SaveOptions, DB};
+
+    #[test]
+    fn stats_match_the_files_on_disk() {
+        let path = "target/test_stats";
+
+        let _ = fs::remove_dir_all(path);
+        let empty: Stats = stats(path).unwrap();
+        assert_eq!(empty, Stats { entry_count: 0, file_size_bytes: 0, backup_count: 0, oldest_backup: None, newest_backup: None });
+
+        let options = SaveOptions { force_backup: true, ..SaveOptions::default() };
+        let mut db: DB<u32> = HashMap::new();
+        for i in 0..3 {
+            db.insert(format!("key{}", i), i);
+            save_db_with_options(path, &db, &options).unwrap();
+        }
+        save_db(path, &db).unwrap();
+
+        let stats: Stats = stats(path).unwrap();
+        let backups = list_backups(path).unwrap();
+        assert_eq!(stats.entry_count, 3);
+        assert_eq!(stats.file_size_bytes, fs::metadata(get_db_path(path)).unwrap().len());
+        assert_eq!(stats.file_size_bytes, "key0=0\nkey1=1\nkey2=2\n".len() as u64);
+        // One backup per forced save but the first, which had no file to back up yet; the last
+        // save didn't change anything, so it took none.
+        assert_eq!(stats.backup_count, 2);
+        assert_eq!(stats.oldest_backup, backups.first().copied());
+        assert_eq!(stats.newest_backup, backups.last().copied());
+        assert!(stats.oldest_backup < stats.newest_backup);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use flate2::read::GzDecoder;
+use serde::de::DeserializeOwned;
+
+use crate::{get_db_path, is_continuation, parse_line, Codec, DBError, JsonCodec, BINARY_MAGIC, CHECKSUM_HEADER, ENCRYPTED_MAGIC, GZIP_MAGIC, PRETTY_HEADER, UTF8_BOM};
+
+/// Like `load_db`, but yields one `(key, value)` at a time while reading the file line by line,
+/// so memory use is bounded by the longest record rather than the whole database. Lines are
+/// classified exactly as `load_db` does; an error is yielded in place of a bad line, and
+/// iteration can carry on past it. An error reading the file itself, such as a truncated gzip
+/// stream, is yielded once and ends the iteration.
+///
+/// Unlike `load_db`, duplicate keys are all yielded, in file order; the last one is the one
+/// `load_db` would keep.
+pub fn load_db_stream<T>(path: &str) -> impl Iterator<Item = Result<(String, T), DBError>> where T: DeserializeOwned {
+    stream_with(path, false, &JsonCodec)
+}
+
+// `load_db_stream` with the knobs of `load_with`: with `lenient`, lines that aren't records are
+// skipped rather than yielded as errors.
+pub(crate) fn stream_with<T, C>(path: &str, lenient: bool, codec: &C) -> impl Iterator<Item = Result<(String, T), DBError>> where T: DeserializeOwned, C: Codec {
+    let (reader, error) = match open_reader(path) {
+        Ok(reader) => (reader, None),
+        Err(e) => (None, Some(e)),
+    };
+    let mut checksummed: bool = false;
+    let mut pretty: bool = false;
+    // Set once the reader fails: `BufRead::lines` would keep yielding the same error forever.
+    let mut failed: bool = false;
+    let mut lines = reader.into_iter().flat_map(|reader| reader.lines()).enumerate().peekable();
+    let records = std::iter::from_fn(move || loop {
+        if failed {
+            return None;
+        }
+        let (index, line) = lines.next()?;
+        let mut line: String = match line {
+            Ok(line) => line,
+            Err(e) => {
+                failed = true;
+                return Some(Err(e.into()));
+            }
+        };
+        checksummed |= index == 0 && line == CHECKSUM_HEADER;
+        pretty |= index == usize::from(checksummed) && line == PRETTY_HEADER;
+        while pretty && let Some((_, Ok(next))) = lines.next_if(|(_, next)| next.as_ref().is_ok_and(|next| is_continuation(next))) {
+            line.push('\n');
+            line.push_str(&next);
+        }
+        if let Some(record) = parse_line(index, &line, lenient, checksummed) {
+            return Some(record.and_then(|record| Ok((record.key, codec.decode(record.value)?))));
+        }
+    });
+    error.map(Err).into_iter().chain(records)
+}
+
+// Opens the data file for buffered reading, decompressing on the fly. `None` means the file
+// doesn't exist, which streams as an empty database.
+pub(crate) fn open_reader(path: &str) -> Result<Option<Box<dyn BufRead>>, DBError> {
+    let file_path = get_db_path(path);
+    let file = match fs::File::open(&file_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut reader = BufReader::new(file);
+    let head: &[u8] = reader.fill_buf()?;
+    if head.starts_with(&GZIP_MAGIC) {
+        let mut reader = BufReader::new(GzDecoder::new(reader));
+        skip_bom(&mut reader)?;
+        return Ok(Some(Box::new(reader)));
+    }
+    if head.starts_with(BINARY_MAGIC) {
+        return Err(DBError::WrongFormat(format!("{} is a binary database; use load_db_binary", file_path)));
+    }
+    if head.starts_with(ENCRYPTED_MAGIC) {
+        return Err(DBError::WrongFormat(format!("{} is an encrypted database; use load_db_encrypted", file_path)));
+    }
+    skip_bom(&mut reader)?;
+    Ok(Some(Box::new(reader)))
+}
+
+fn skip_bom<R>(reader: &mut R) -> Result<(), DBError> where R: BufRead {
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_db, save_db_with_options, SaveOptions, DB};
+
+    #[test]
+    fn streams_every_entry() {
+        let path = "target/test_stream_entries";
+
+        let _ = fs::remove_dir_all(path);
+        assert_eq!(load_db_stream::<u32>(path).count(), 0);
+
+        let db: DB<u32> = (0..100).map(|i| (format!("key{}", i), i)).collect();
+        for options in [SaveOptions::default(), SaveOptions { compress: true, checksums: true, ..SaveOptions::default() }] {
+            save_db_with_options(path, &db, &options).unwrap();
+            let streamed: DB<u32> = load_db_stream(path).collect::<Result<_, _>>().expect("streaming should succeed");
+            assert_eq!(streamed, db);
+        }
+    }
+
+    #[test]
+    fn truncated_gzip_ends_the_stream() {
+        let path = "target/test_stream_truncated_gzip";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: DB<u32> = (0..5000).map(|i| (format!("key{}", i), i)).collect();
+        save_db_with_options(path, &db, &SaveOptions { compress: true, ..SaveOptions::default() }).unwrap();
+        let bytes: Vec<u8> = fs::read(get_db_path(path)).unwrap();
+        fs::write(get_db_path(path), &bytes[..bytes.len() / 2]).unwrap();
+
+        let items: Vec<Result<(String, u32), DBError>> = load_db_stream(path).take(100_000).collect();
+        assert!(items.len() < 5000);
+        assert_eq!(items.iter().filter(|item| item.is_err()).count(), 1);
+        assert!(matches!(items.last(), Some(Err(DBError::Io(_)))));
+        assert!(matches!(load_db::<u32>(path), Err(DBError::Io(_))));
+    }
+
+    #[test]
+    fn bad_lines_yield_errors_in_place() {
+        let path = "target/test_stream_errors";
+
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        fs::write(get_db_path(path), "# header\na=1\nbroken\nb=2\nc=\"not a number\"\n").unwrap();
+
+        let items: Vec<Result<(String, u32), DBError>> = load_db_stream(path).collect();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].as_ref().unwrap(), &("a".to_string(), 1));
+        assert!(matches!(items[1], Err(DBError::MalformedLine { line: 3, .. })));
+        assert_eq!(items[2].as_ref().unwrap(), &("b".to_string(), 2));
+        assert!(matches!(items[3], Err(DBError::Serde(_))));
+        assert!(load_db::<u32>(path).is_err());
+    }
+}
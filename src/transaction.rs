@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::{DBError, Database};
+
+/// The changes staged inside a `Database::transaction`. Reads see the database as it would be
+/// if the transaction committed now; nothing reaches the database itself until it does.
+#[derive(Debug)]
+pub struct Txn<'a, T> where T: Serialize {
+    db: &'a Database<T>,
+    // The latest staged change per key: `Some` to insert, `None` to remove.
+    changes: HashMap<String, Option<T>>,
+}
+
+impl<T> Txn<'_, T> where T: Serialize + DeserializeOwned {
+    pub fn get(&self, key: &str) -> Option<&T> {
+        let key: String = self.resolve(key);
+        match self.changes.get(&key) {
+            Some(change) => change.as_ref(),
+            None => self.db.data().get(&key),
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Stages an insert. Fails without staging anything if the key doesn't pass
+    /// `DatabaseOptions::key_validator`.
+    pub fn insert(&mut self, key: impl Into<String>, value: T) -> Result<(), DBError> {
+        let key: String = key.into();
+        self.db.options().key_validator.validate(&key)?;
+        self.changes.insert(self.resolve(&key), Some(value));
+        Ok(())
+    }
+
+    /// Stages a removal, returning whether there was a value to remove.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let existed: bool = self.contains_key(key);
+        self.changes.insert(self.resolve(key), None);
+        existed
+    }
+
+    // The key a change to `key` applies to: with `case_insensitive`, a differently cased key
+    // that's already staged or stored.
+    fn resolve(&self, key: &str) -> String {
+        if !self.db.options().case_insensitive {
+            return key.to_string();
+        }
+        let lowercase: String = key.to_lowercase();
+        match self.changes.keys().find(|staged| staged.to_lowercase() == lowercase) {
+            Some(staged) => staged.clone(),
+            None => self.db.stored_key(key).to_string(),
+        }
+    }
+}
+
+impl<T> Database<T> where T: Serialize + DeserializeOwned {
+    /// Runs `f` against a `Txn` and, only if it returns `Ok`, applies every change it staged and
+    /// saves, so the changes reach memory and disk together or not at all. The save goes through
+    /// the usual temp file and rename, so a crash part-way leaves the previous file in place. If
+    /// `f` returns `Err`, or the save fails, the database is left exactly as it was; a failed save
+    /// is returned through `E`'s `From<DBError>`.
+    ///
+    /// Unsaved changes made before the transaction are saved along with it. Committed changes are
+    /// reported to `on_change` listeners, but aren't logged to the WAL, which the save empties
+    /// anyway, and don't evict entries beyond `max_entries`.
+    pub fn transaction<E, F>(&mut self, f: F) -> Result<(), E> where F: FnOnce(&mut Txn<'_, T>) -> Result<(), E>, E: From<DBError> {
+        let mut txn: Txn<'_, T> = Txn { db: self, changes: HashMap::new() };
+        f(&mut txn)?;
+        let changes: HashMap<String, Option<T>> = txn.changes;
+        self.commit_changes(changes)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{load_db, DB};
+
+    #[test]
+    fn failed_transaction_changes_nothing() {
+        let path = "target/test_transaction_rollback";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        db.save().unwrap();
+
+        let result: Result<(), DBError> = db.transaction(|txn| {
+            txn.insert("a", 10)?;
+            txn.remove("b");
+            txn.insert("c", 3)?;
+            assert_eq!(txn.get("a"), Some(&10));
+            assert!(!txn.contains_key("b"));
+            Err(DBError::NotFound("the rest of the batch".to_string()))
+        });
+
+        assert!(matches!(result, Err(DBError::NotFound(_))));
+        assert_eq!(db.get("a"), Some(&1));
+        assert_eq!(db.get("b"), Some(&2));
+        assert!(db.get("c").is_none());
+        assert!(!db.is_dirty());
+        assert_eq!(load_db::<u32>(path).unwrap(), DB::from([("a".to_string(), 1), ("b".to_string(), 2)]));
+
+        // An invalid key fails the transaction the same way.
+        let result: Result<(), DBError> = db.transaction(|txn| {
+            txn.insert("x", 9)?;
+            txn.insert("", 0)
+        });
+        assert!(matches!(result, Err(DBError::InvalidKey { .. })));
+        assert!(db.get("x").is_none());
+    }
+
+    #[test]
+    fn successful_transaction_applies_and_saves_everything() {
+        let path = "target/test_transaction_commit";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: Database<u32> = Database::open(path).unwrap();
+        db.insert("a", 1).unwrap();
+        db.insert("b", 2).unwrap();
+        db.save().unwrap();
+
+        db.transaction::<DBError, _>(|txn| {
+            txn.insert("a", 10)?;
+            assert!(txn.remove("b"));
+            txn.insert("c", 3)?;
+            txn.remove("c");
+            txn.insert("d", 4)
+        }).unwrap();
+
+        let expected: DB<u32> = DB::from([("a".to_string(), 10), ("d".to_string(), 4)]);
+        assert_eq!(db.data(), &expected);
+        assert!(!db.is_dirty());
+        assert_eq!(load_db::<u32>(path).unwrap(), expected);
+    }
+}
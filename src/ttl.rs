@@ -0,0 +1,180 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{load_db, save_db, DBError, Database, SharedDatabase, DB};
+
+/// A value with an optional expiry time, stored on disk as
+/// `{"expires_at": "<rfc3339>", "value": ...}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expiring<T> {
+    #[serde(default)]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub value: T,
+}
+
+impl<T> Expiring<T> {
+    /// A value that never expires.
+    pub fn new(value: T) -> Self {
+        Expiring { expires_at: None, value }
+    }
+
+    /// A value that expires `ttl` from now.
+    pub fn with_ttl(value: T, ttl: TimeDelta) -> Self {
+        Expiring { expires_at: Some(chrono::Local::now().fixed_offset() + ttl), value }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(&chrono::Local::now().fixed_offset())
+    }
+
+    pub fn is_expired_at(&self, now: &DateTime<FixedOffset>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= *now)
+    }
+}
+
+/// Inserts `value` so that it expires `ttl` from now.
+pub fn insert_with_ttl<T>(db: &mut DB<Expiring<T>>, key: impl Into<String>, value: T, ttl: TimeDelta) -> Option<Expiring<T>> {
+    db.insert(key.into(), Expiring::with_ttl(value, ttl))
+}
+
+/// Like `load_db`, but drops entries whose expiry has passed.
+pub fn load_db_expiring<T>(path: &str) -> Result<DB<Expiring<T>>, DBError> where T: DeserializeOwned {
+    let mut db: DB<Expiring<T>> = load_db(path)?;
+    let now = chrono::Local::now().fixed_offset();
+    db.retain(|_, entry| !entry.is_expired_at(&now));
+    Ok(db)
+}
+
+/// Like `save_db`, but leaves out expired entries so they don't accumulate in the file.
+pub fn save_db_expiring<T>(path: &str, contents: &DB<Expiring<T>>) -> Result<(), DBError> where T: Serialize {
+    let now = chrono::Local::now().fixed_offset();
+    let live: DB<&Expiring<T>> = contents.iter()
+        .filter(|(_, entry)| !entry.is_expired_at(&now))
+        .map(|(key, entry)| (key.clone(), entry))
+        .collect();
+    save_db(path, &live)
+}
+
+impl<T> Database<Expiring<T>> where T: Serialize + DeserializeOwned {
+    /// Removes the entries whose expiry has passed, as if by `remove`, and returns how many.
+    pub fn remove_expired(&mut self) -> usize {
+        let now = chrono::Local::now().fixed_offset();
+        let expired: Vec<String> = self.data().iter()
+            .filter(|(_, entry)| entry.is_expired_at(&now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+}
+
+/// Keeps a `SharedDatabase::start_ttl_sweeper` running. Dropping it stops the sweeper and waits
+/// for its thread to finish.
+#[derive(Debug)]
+pub struct SweeperHandle {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for SweeperHandle {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> SharedDatabase<Expiring<T>> where T: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Calls `Database::remove_expired` every `interval` on a background thread, so expired
+    /// entries don't linger in memory between loads. The removals mark the handle dirty but
+    /// aren't saved by the sweeper; combine it with `enable_autosave` to persist them.
+    pub fn start_ttl_sweeper(&self, interval: Duration) -> SweeperHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let db = self.clone();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Ok(mut guard) = db.write() else { return };
+                guard.remove_expired();
+            }
+        });
+        SweeperHandle { stop, thread: Some(thread) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn expired_entries_disappear_on_load() {
+        let path = "target/test_ttl_expiry";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<Expiring<String>> = HashMap::new();
+        insert_with_ttl(&mut db, "short", "gone soon".to_string(), TimeDelta::seconds(1));
+        insert_with_ttl(&mut db, "long", "still here".to_string(), TimeDelta::hours(1));
+        db.insert("forever".to_string(), Expiring::new("never expires".to_string()));
+        save_db_expiring(path, &db).expect("saving should succeed");
+
+        let loaded: DB<Expiring<String>> = load_db_expiring(path).expect("loading should succeed");
+        assert_eq!(loaded.len(), 3);
+
+        thread::sleep(Duration::from_millis(1100));
+
+        let loaded: DB<Expiring<String>> = load_db_expiring(path).expect("loading should succeed");
+        assert_eq!(loaded.len(), 2);
+        assert!(!loaded.contains_key("short"));
+        assert_eq!(loaded["long"].value, "still here");
+        assert_eq!(loaded["forever"].value, "never expires");
+    }
+
+    #[test]
+    fn expired_entries_are_pruned_on_save() {
+        let path = "target/test_ttl_prune";
+
+        let _ = fs::remove_dir_all(path);
+
+        let mut db: DB<Expiring<u32>> = HashMap::new();
+        insert_with_ttl(&mut db, "stale", 1, TimeDelta::seconds(-1));
+        insert_with_ttl(&mut db, "fresh", 2, TimeDelta::hours(1));
+        save_db_expiring(path, &db).expect("saving should succeed");
+
+        // A plain load sees exactly what's in the file.
+        let raw: DB<Expiring<u32>> = load_db(path).expect("loading should succeed");
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw["fresh"].value, 2);
+    }
+
+    #[test]
+    fn sweeper_removes_expired_entries_in_memory() {
+        let path = "target/test_ttl_sweeper";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: SharedDatabase<Expiring<u32>> = SharedDatabase::open(path).unwrap();
+        db.insert("short", Expiring::with_ttl(1, TimeDelta::milliseconds(50))).unwrap();
+        db.insert("long", Expiring::with_ttl(2, TimeDelta::hours(1))).unwrap();
+        db.save().unwrap();
+
+        let handle = db.start_ttl_sweeper(Duration::from_millis(20));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(db.len().unwrap(), 1);
+        assert!(db.get("short").unwrap().is_none());
+        assert!(db.read().unwrap().is_dirty());
+        drop(handle);
+
+        // Nothing was saved behind the caller's back.
+        let raw: DB<Expiring<u32>> = load_db(path).unwrap();
+        assert_eq!(raw.len(), 2);
+    }
+}
@@ -0,0 +1,67 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{ensure_db_dir, get_one, load_db, options_preserving_format, save_locked, DBError, FileLock, JsonCodec, KeyValidator, DB, DEFAULT_LOCK_TIMEOUT};
+
+/// A value with a version number that goes up by one on every `update_if_version`, stored on
+/// disk as `{"version": 3, "value": ...}`. A key that has never been written is at version 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub version: u64,
+    pub value: T,
+}
+
+/// Reads `key` along with its version, to pass to a later `update_if_version`.
+pub fn get_versioned<T>(path: &str, key: &str) -> Result<Option<Versioned<T>>, DBError> where T: DeserializeOwned {
+    get_one(path, key)
+}
+
+/// Writes `new` under `key` only if its version on disk is still `expected_version`, and returns
+/// the new version. If another writer got there first, fails with `DBError::VersionConflict` and
+/// writes nothing, so the caller can re-read and retry instead of silently overwriting.
+///
+/// Use 0 as `expected_version` to create a key that must not exist yet.
+pub fn update_if_version<T>(path: &str, key: &str, expected_version: u64, new: T) -> Result<u64, DBError> where T: Serialize + DeserializeOwned {
+    KeyValidator::default().validate(key)?;
+    ensure_db_dir(path)?;
+    let _lock = FileLock::acquire(path, DEFAULT_LOCK_TIMEOUT)?;
+    let options = options_preserving_format(path)?;
+    let mut db: DB<Versioned<T>> = load_db(path)?;
+    let actual: u64 = db.get(key).map_or(0, |current| current.version);
+    if actual != expected_version {
+        return Err(DBError::VersionConflict { key: key.to_string(), expected: expected_version, actual });
+    }
+    let version: u64 = actual + 1;
+    db.insert(key.to_string(), Versioned { version, value: new });
+    save_locked(path, &db, &options, &JsonCodec)?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn stale_updates_are_rejected() {
+        let path = "target/test_versioned_conflict";
+
+        let _ = fs::remove_dir_all(path);
+
+        assert_eq!(update_if_version(path, "doc", 0, "draft".to_string()).unwrap(), 1);
+
+        // Two readers see the same version...
+        let first: Versioned<String> = get_versioned(path, "doc").unwrap().unwrap();
+        let second: Versioned<String> = get_versioned(path, "doc").unwrap().unwrap();
+        assert_eq!(first.version, 1);
+
+        // ...the first update wins, and the second, now stale, is refused.
+        assert_eq!(update_if_version(path, "doc", first.version, "first".to_string()).unwrap(), 2);
+        let err = update_if_version(path, "doc", second.version, "second".to_string()).unwrap_err();
+        assert!(matches!(err, DBError::VersionConflict { expected: 1, actual: 2, .. }));
+
+        let current: Versioned<String> = get_versioned(path, "doc").unwrap().unwrap();
+        assert_eq!(current, Versioned { version: 2, value: "first".to_string() });
+        assert!(matches!(update_if_version(path, "doc", 0, "again".to_string()), Err(DBError::VersionConflict { .. })));
+    }
+}
@@ -0,0 +1,168 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::database::Fingerprint;
+use crate::modified::load_tracked;
+use crate::{DBError, SharedDatabase, DB};
+
+// A save shows up as several events (the rename, plus whatever the editor or OS adds); wait for
+// this long a quiet period before reloading, so one save means one reload.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+enum Message {
+    Changed,
+    Stop,
+}
+
+/// Keeps a `SharedDatabase::watch` running. Dropping it stops the watcher and waits for its
+/// thread to finish.
+#[derive(Debug)]
+pub struct WatchHandle {
+    watcher: Option<RecommendedWatcher>,
+    stop: Sender<Message>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.watcher.take();
+        let _ = self.stop.send(Message::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> SharedDatabase<T> where T: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Reloads the in-memory map whenever the data file changes on disk, whether another process
+    /// saved it or someone edited it by hand, then calls `callback` with the new contents. Saves
+    /// through this handle don't reload it. While the handle has unsaved changes, outside changes
+    /// aren't reloaded either, rather than discarding them; the next change after a save is.
+    ///
+    /// The directory is watched rather than the file, since saves replace the file by renaming
+    /// a temp file over it. A change that doesn't parse is skipped, keeping the current map.
+    /// The callback runs on the watcher's thread with the database read-locked, so it must not
+    /// call back into the database.
+    pub fn watch<F>(&self, mut callback: F) -> Result<WatchHandle, DBError> where F: FnMut(&DB<T>) + Send + 'static {
+        if self.read()?.is_in_memory() {
+            return Err(DBError::Unsupported("watching an in-memory database".to_string()));
+        }
+        let path: PathBuf = self.read()?.path().to_path_buf();
+        let tracked: bool = self.read()?.options().track_modified;
+        let dir: PathBuf = if path.as_os_str().is_empty() { PathBuf::from(".") } else { path };
+        fs::create_dir_all(&dir)?;
+
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let events = sender.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event && touches_data_file(&event) {
+                let _ = events.send(Message::Changed);
+            }
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        let db = self.clone();
+        let thread = thread::spawn(move || {
+            while let Ok(Message::Changed) = receiver.recv() {
+                loop {
+                    match receiver.recv_timeout(DEBOUNCE) {
+                        Ok(Message::Changed) => continue,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                let Ok(guard) = db.read() else { return };
+                let changed: Option<Option<Fingerprint>> = if guard.is_dirty() { None } else { guard.changed_on_disk() };
+                drop(guard);
+                let Some(synced) = changed else { continue };
+                let Some(dir) = dir.to_str() else { return };
+                let Ok((data, modified)) = load_tracked(dir, tracked) else { continue };
+                let Ok(mut guard) = db.write() else { return };
+                // Changes made while the file was being loaded win over it.
+                if guard.is_dirty() {
+                    continue;
+                }
+                guard.replace_data(data, modified, synced);
+                drop(guard);
+                let Ok(guard) = db.read() else { return };
+                callback(guard.data());
+            }
+        });
+        Ok(WatchHandle { watcher: Some(watcher), stop: sender, thread: Some(thread) })
+    }
+}
+
+fn touches_data_file(event: &notify::Event) -> bool {
+    let changes = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_));
+    changes && event.paths.iter().any(|path| path.file_name() == Some(OsStr::new("memory.db")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::{get_wal_path, save_db, Database, DatabaseOptions};
+
+    #[test]
+    fn external_saves_are_reloaded() {
+        let path = "target/test_watch_reload";
+
+        let _ = fs::remove_dir_all(path);
+
+        let db: SharedDatabase<u32> = SharedDatabase::open(path).unwrap();
+        let (sender, reloads) = mpsc::channel::<DB<u32>>();
+        let _handle = db.watch(move |data| {
+            let _ = sender.send(data.clone());
+        }).expect("watching should succeed");
+
+        let mut external: DB<u32> = HashMap::new();
+        external.insert("a".to_string(), 1);
+        save_db(path, &external).unwrap();
+
+        let reloaded = reloads.recv_timeout(Duration::from_secs(5)).expect("the watcher should fire");
+        assert_eq!(reloaded, external);
+        assert_eq!(db.get("a").unwrap(), Some(1));
+        // The temp write and rename of a single save are debounced into one reload.
+        assert!(reloads.recv_timeout(DEBOUNCE * 5).is_err());
+    }
+
+    #[test]
+    fn own_saves_and_unsaved_changes_are_kept() {
+        let path = "target/test_watch_own_saves";
+
+        let _ = fs::remove_dir_all(path);
+
+        let options = DatabaseOptions { wal: true, ..DatabaseOptions::default() };
+        let db: SharedDatabase<u32> = Database::open_with_options(path, options).unwrap().into();
+        let (sender, reloads) = mpsc::channel::<DB<u32>>();
+        let _handle = db.watch(move |data| {
+            let _ = sender.send(data.clone());
+        }).expect("watching should succeed");
+
+        db.insert("a", 1).unwrap();
+        db.save().unwrap();
+        // Made within the debounce of the save above, and only in memory and the log.
+        db.insert("b", 2).unwrap();
+        assert!(reloads.recv_timeout(DEBOUNCE * 5).is_err());
+        assert_eq!(db.get("b").unwrap(), Some(2));
+        assert!(db.read().unwrap().is_dirty());
+        assert!(fs::read_to_string(get_wal_path(path)).unwrap().contains("+b=2"));
+
+        // Nor is an outside save while there are unsaved changes.
+        save_db(path, &HashMap::from([("c".to_string(), 3)])).unwrap();
+        assert!(reloads.recv_timeout(DEBOUNCE * 5).is_err());
+        assert_eq!(db.get("b").unwrap(), Some(2));
+
+        db.save().unwrap();
+        save_db(path, &HashMap::from([("d".to_string(), 4)])).unwrap();
+        let reloaded = reloads.recv_timeout(Duration::from_secs(5)).expect("the watcher should fire");
+        assert_eq!(reloaded, HashMap::from([("d".to_string(), 4)]));
+    }
+}